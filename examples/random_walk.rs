@@ -0,0 +1,46 @@
+// A tiny headless bot that wanders around at random, to show off driving the engine without any
+// UI. Run with `cargo run --example random_walk --no-default-features -- [seed]`.
+extern crate cyberphage;
+extern crate rand;
+
+use std::env;
+
+use cyberphage::game::{Action, Direction, Game, Recorder};
+use rand::Rng;
+
+fn main() {
+    let seed = env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+    let mut rng = rand::thread_rng();
+    let mut game = Recorder::new(seed);
+
+    for _ in 0..10000 {
+        if game.update() {
+            continue;
+        }
+        match *game.game() {
+            Game::Level(_) => {}
+            _ => { break; }
+        }
+        let dir = *rng.choose(&[Direction::Up, Direction::Down, Direction::Left, Direction::Right])
+            .unwrap();
+        game.step(Action::Move(dir));
+    }
+
+    match *game.game() {
+        Game::Level(ref level) => {
+            println!("Still wandering around level {}", level.depth());
+            for cv in level.player_deck() {
+                println!("{:?} ({:?})", cv.card, cv.status);
+            }
+        }
+        Game::Transition(_) => println!("Between levels"),
+        Game::GameOver(ref death, _) => {
+            println!(
+                "Deleted by a {:?} on level {} after {} turns",
+                death.killer, death.level, death.turns,
+            );
+        }
+        Game::Victory(_) => println!("Won!"),
+    }
+    println!("Recorded {} inputs", game.replay().len());
+}
@@ -0,0 +1,117 @@
+// Every colour the game uses, in one place. The terminal front end hands these to cursive and
+// leaves the exact shades up to the terminal, while the web front end has no terminal to ask and
+// turns them into RGB with `rgb`.
+use cursive::theme::{BaseColor, Color, ColorStyle, PaletteColor, Style, Theme};
+
+use game::{EntityType, Glyph, LogKind, Tile};
+
+// Things about to delete you
+pub const DANGER: Color = Color::Light(BaseColor::Red);
+pub const EXIT: Color = Color::Dark(BaseColor::Green);
+const FRAGMENT: Color = Color::Dark(BaseColor::Cyan);
+// Anything out of sight that's only remembered
+const REMEMBERED: Color = Color::Light(BaseColor::Black);
+
+pub fn theme() -> Theme {
+    let mut theme = Theme::default();
+    theme.palette[PaletteColor::Secondary] = Color::Dark(BaseColor::Blue);
+    theme.palette[PaletteColor::Tertiary] = Color::Dark(BaseColor::Cyan);
+    theme
+}
+
+fn on_view(front: Color) -> ColorStyle {
+    ColorStyle::new(front, PaletteColor::View)
+}
+
+// Solid squares are drawn as blocks of colour
+fn block(back: Color) -> ColorStyle {
+    ColorStyle::new(PaletteColor::View, back)
+}
+
+pub fn tile_style(tile: Tile) -> ColorStyle {
+    match tile {
+        Tile::Floor => on_view(Color::Dark(BaseColor::Blue)),
+        Tile::Wall | Tile::Secret => block(Color::Dark(BaseColor::Blue)),
+        Tile::Door => on_view(Color::Dark(BaseColor::Yellow)),
+        Tile::Exit => block(EXIT),
+    }
+}
+
+pub fn entity_style(typ: EntityType) -> ColorStyle {
+    match typ {
+        EntityType::Player => on_view(Color::Dark(BaseColor::Black)),
+        EntityType::Defender => on_view(Color::Dark(BaseColor::Yellow)),
+        EntityType::Hunter => on_view(Color::Dark(BaseColor::Magenta)),
+        EntityType::Reaper => on_view(DANGER),
+        EntityType::Core => block(Color::Light(BaseColor::Magenta)),
+        EntityType::UnknownThing => on_view(REMEMBERED),
+    }
+}
+
+// Visible squares only get their colours while the map has focus, so it's obvious when keys are
+// going somewhere else
+pub fn glyph_style(glyph: &Glyph, focused: bool) -> ColorStyle {
+    match *glyph {
+        Glyph::Unknown => ColorStyle::primary(),
+        Glyph::Visible(..) if !focused => unfocused_style(glyph),
+        Glyph::Visible(_, Some(typ), _) => entity_style(typ),
+        Glyph::Visible(_, None, Some(_)) => on_view(FRAGMENT),
+        Glyph::Visible(tile, None, None) => tile_style(tile),
+        Glyph::Remembered(Tile::Wall, None) | Glyph::Remembered(Tile::Secret, None) => {
+            block(REMEMBERED)
+        }
+        Glyph::Remembered(_, _) => on_view(REMEMBERED),
+    }
+}
+
+// Everything visible in the plain text colour, with walls still drawn as blocks
+fn unfocused_style(glyph: &Glyph) -> ColorStyle {
+    match *glyph {
+        Glyph::Visible(Tile::Wall, None, None) | Glyph::Visible(Tile::Secret, None, None) => {
+            ColorStyle::new(PaletteColor::View, PaletteColor::Primary)
+        }
+        _ => ColorStyle::primary(),
+    }
+}
+
+pub fn log_style(kind: LogKind) -> Style {
+    match kind {
+        LogKind::Attack => Color::Dark(BaseColor::Yellow).into(),
+        LogKind::Damage => DANGER.into(),
+        LogKind::CardActivated => Color::Dark(BaseColor::Cyan).into(),
+        LogKind::CardDiscarded => Color::Dark(BaseColor::Magenta).into(),
+        LogKind::Kill => Color::Dark(BaseColor::Red).into(),
+        LogKind::LevelEvent => EXIT.into(),
+        LogKind::System => ColorStyle::secondary().into(),
+    }
+}
+
+/// The shade to draw a colour with where there's no terminal to pick one, or None for the
+/// terminal's default colour.
+pub fn rgb(color: Color) -> Option<[u8; 3]> {
+    Some(match color {
+        Color::TerminalDefault => { return None; }
+        Color::Dark(color) => match color {
+            BaseColor::Black => [0x00, 0x00, 0x00],
+            BaseColor::Red => [0x80, 0x00, 0x00],
+            BaseColor::Green => [0x00, 0x80, 0x00],
+            BaseColor::Yellow => [0x80, 0x80, 0x00],
+            BaseColor::Blue => [0x00, 0x00, 0x80],
+            BaseColor::Magenta => [0x80, 0x00, 0x80],
+            BaseColor::Cyan => [0x00, 0x80, 0x80],
+            BaseColor::White => [0xc0, 0xc0, 0xc0],
+        },
+        Color::Light(color) => match color {
+            BaseColor::Black => [0x80, 0x80, 0x80],
+            BaseColor::Red => [0xff, 0x00, 0x00],
+            BaseColor::Green => [0x00, 0xff, 0x00],
+            BaseColor::Yellow => [0xff, 0xff, 0x00],
+            BaseColor::Blue => [0x00, 0x00, 0xff],
+            BaseColor::Magenta => [0xff, 0x00, 0xff],
+            BaseColor::Cyan => [0x00, 0xff, 0xff],
+            BaseColor::White => [0xff, 0xff, 0xff],
+        },
+        Color::Rgb(r, g, b) => [r, g, b],
+        Color::RgbLowRes(r, g, b) => [r*51, g*51, b*51],
+    })
+}
@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::*;
+
+use rand::Rng;
+
+// Squares with something standing in them cost extra to path through, so enemies route around
+// each other instead of queueing up behind one another
+const OCCUPIED_COST: i32 = 5;
+
+// Walking costs to the nearest source, over everything that isn't solid
+pub type DistanceMap = Vec<Vec<Option<i32>>>;
+
+impl Mind {
+    pub fn new(t: EntityType) -> Mind {
+        Mind { state: resting_state(t), goal: Goal::Wait, memory: None }
+    }
+}
+
+// What an enemy goes back to doing once it's forgotten about the player
+fn resting_state(t: EntityType) -> AiState {
+    match t {
+        EntityType::Hunter => AiState::Patrol,
+        _ => AiState::Idle,
+    }
+}
+
+// How many turns an enemy keeps looking for the player after losing sight of them
+fn memory_span(t: EntityType) -> i32 {
+    match t {
+        EntityType::Hunter => 30,
+        EntityType::Reaper => 15,
+        _ => 8,
+    }
+}
+
+pub fn take_turn(level: &mut Level, entity: Entity) {
+    if entity == PLAYER {
+        return;
+    }
+    let t = level.type_of(entity);
+    if t == EntityType::UnknownThing || t == EntityType::Core { return; }
+    let pos = match level.positions.get(&entity) {
+        Some(&pos) => pos,
+        None => { return; }
+    };
+
+    let mut mind = level.minds.get(&entity).cloned().unwrap_or_else(|| Mind::new(t));
+    let player_pos = level.positions.get(&PLAYER).cloned();
+    let sees_player = match player_pos {
+        Some(player_pos) if level.can_see(entity, player_pos) => {
+            mind.memory = Some(Memory { pos: player_pos, age: 0 });
+            true
+        }
+        _ => false,
+    };
+    if !sees_player {
+        if let Some(ref mut memory) = mind.memory {
+            memory.age += 1;
+        }
+        if mind.memory.map(|m| m.age > memory_span(t)).unwrap_or(false) {
+            mind.memory = None;
+        }
+    }
+
+    mind.state = next_state(level, entity, t, &mind, sees_player);
+    mind.goal = pick_goal(level, entity, pos, &mind, sees_player);
+    let action = goal_action(level, entity, pos, mind.goal);
+    // Nowhere to go, so pick something else next turn rather than waiting around forever
+    if action == Action::Wait && mind.goal != Goal::Wait {
+        mind.goal = Goal::Wait;
+    }
+    level.minds.insert(entity, mind);
+    level.do_action(entity, action);
+}
+
+fn next_state(level: &Level, entity: Entity, t: EntityType, mind: &Mind, sees_player: bool) -> AiState {
+    let (hand, deck) = hand_size(level, entity);
+    // Run off with only a card or two left, and only come back once most of them are recovered
+    let hurt = hand * 3 < deck;
+    let recovered = hand * 3 >= deck * 2;
+    match mind.state {
+        AiState::Flee if !recovered => AiState::Flee,
+        _ if hurt && mind.memory.is_some() => AiState::Flee,
+        _ if sees_player => AiState::Hunt,
+        AiState::Hunt | AiState::Investigate | AiState::Flee if mind.memory.is_some() => {
+            AiState::Investigate
+        }
+        AiState::Hunt | AiState::Investigate | AiState::Flee => resting_state(t),
+        state => state,
+    }
+}
+
+fn pick_goal(level: &mut Level, entity: Entity, pos: Position, mind: &Mind, sees_player: bool) -> Goal {
+    let (hand, deck) = hand_size(level, entity);
+    match mind.state {
+        AiState::Idle => if hand < deck { Goal::Rest } else { Goal::Wait },
+        AiState::Patrol => match mind.goal {
+            Goal::Move(target) if target != pos => Goal::Move(target),
+            _ if hand < deck => Goal::Rest,
+            _ => patrol_target(level).map(Goal::Move).unwrap_or(Goal::Wait),
+        },
+        AiState::Investigate => match mind.memory {
+            Some(memory) if memory.pos != pos => Goal::Move(memory.pos),
+            // Nothing here, so poke around nearby until the memory fades
+            _ => {
+                let nearby: Vec<_> = DIRECTIONS.iter().map(|&dir| pos.step(dir))
+                    .filter(|&p| level.is_open(p)).collect();
+                level.rng.choose(&nearby).cloned().map(Goal::Move).unwrap_or(Goal::Wait)
+            }
+        },
+        AiState::Hunt => Goal::Attack(PLAYER),
+        AiState::Flee => match level.positions.get(&PLAYER) {
+            Some(&player_pos) if sees_player => {
+                let toward = dijkstra_map(level, &[(player_pos, 0)]);
+                // Scaling the distances past -1 makes running past the player to get somewhere
+                // further away worth it, rather than getting cornered
+                let sources: Vec<_> = cells(&toward).map(|(p, d)| (p, -d * 6 / 5)).collect();
+                let away = dijkstra_map(level, &sources);
+                downhill(level, &away, pos, None).map(|dir| Goal::Move(pos.step(dir)))
+                    .unwrap_or(Goal::Rest)
+            }
+            _ => Goal::Rest,
+        },
+    }
+}
+
+// Somewhere new to wander off to, giving up for the turn if nothing turns up
+fn patrol_target(level: &mut Level) -> Option<Position> {
+    // Nothing inside the border to pick from
+    if level.width() <= 2 || level.height() <= 2 {
+        return None;
+    }
+    for _ in 0..10 {
+        let pos = Position {
+            x: level.rng.gen_range(1, level.width() - 1),
+            y: level.rng.gen_range(1, level.height() - 1),
+        };
+        if level.is_open(pos) {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+fn goal_action(level: &mut Level, entity: Entity, pos: Position, goal: Goal) -> Action {
+    let target = match goal {
+        Goal::Wait => { return Action::Wait; }
+        Goal::Rest => { return Action::Rest; }
+        Goal::Move(target) => target,
+        Goal::Attack(target) => {
+            if let Some(action) = tactics::attack_action(level, entity, target) {
+                return action;
+            }
+            match level.positions.get(&target) {
+                Some(&target) => target,
+                None => { return Action::Wait; }
+            }
+        }
+    };
+    if target == pos {
+        return Action::Wait;
+    }
+    let map = dijkstra_map(level, &[(target, 0)]);
+    match downhill(level, &map, pos, Some(target)) {
+        Some(dir) => Action::Move(dir),
+        None => Action::Wait,
+    }
+}
+
+// Picks a random direction that gets closer according to the map, avoiding anything in the way
+// other than the target itself
+pub fn downhill(level: &mut Level, map: &DistanceMap, pos: Position, target: Option<Position>) -> Option<Direction> {
+    let here = distance(map, pos).unwrap_or(i32::MAX);
+    let mut best = Vec::new();
+    let mut best_dist = here;
+    for &dir in DIRECTIONS {
+        let next = pos.step(dir);
+        if Some(next) != target && !level.is_open(next) {
+            continue;
+        }
+        match distance(map, next) {
+            Some(d) if d < best_dist => {
+                best_dist = d;
+                best = vec![dir];
+            }
+            Some(d) if d == best_dist && d < here => { best.push(dir); }
+            _ => {}
+        }
+    }
+    level.rng.choose(&best).cloned()
+}
+
+fn hand_size(level: &Level, entity: Entity) -> (usize, usize) {
+    level.decks.get(&entity).map(|deck| {
+        (deck.iter().filter(|c| c.status.in_hand()).count(), deck.len())
+    }).unwrap_or((0, 0))
+}
+
+pub fn distance(map: &DistanceMap, pos: Position) -> Option<i32> {
+    map.get(pos.y as usize).and_then(|row| row.get(pos.x as usize)).and_then(|&d| d)
+}
+
+fn cells<'a>(map: &'a DistanceMap) -> impl Iterator<Item = (Position, i32)> + 'a {
+    map.iter().enumerate().flat_map(|(y, row)| row.iter().enumerate().filter_map(move |(x, &d)| {
+        d.map(|d| (Position { x: x as i32, y: y as i32 }, d))
+    }))
+}
+
+pub fn dijkstra_map(level: &Level, sources: &[(Position, i32)]) -> DistanceMap {
+    let mut map = vec![vec![None; level.width() as usize]; level.height() as usize];
+    let mut pending = BinaryHeap::new();
+    for &(pos, cost) in sources {
+        pending.push((Reverse(cost), pos.x, pos.y));
+    }
+    while let Some((Reverse(cost), x, y)) = pending.pop() {
+        let pos = Position { x, y };
+        if distance(&map, pos).is_some() {
+            continue;
+        }
+        map[y as usize][x as usize] = Some(cost);
+        for &dir in DIRECTIONS {
+            let next = pos.step(dir);
+            let sq = level.get_sq(next);
+            if sq.tile.is_solid() || distance(&map, next).is_some() {
+                continue;
+            }
+            let step = if sq.entity.is_some() { OCCUPIED_COST } else { 1 };
+            pending.push((Reverse(cost + step), next.x, next.y));
+        }
+    }
+    map
+}
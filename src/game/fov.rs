@@ -0,0 +1,106 @@
+use super::Position;
+
+// Symmetric shadowcasting, as described at https://www.albertford.com/shadowcasting/
+//
+// Everything the origin can see can see the origin back, so the same code works for the player
+// looking around and for enemies deciding whether they've spotted the player. Slopes are kept as
+// fractions so nothing depends on floating point rounding.
+
+#[derive(Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    fn min_col(&self) -> i32 {
+        // depth * start, rounded with ties going up
+        floor_div(2 * self.depth * self.start.num + self.start.den, 2 * self.start.den)
+    }
+
+    fn max_col(&self) -> i32 {
+        // depth * end, rounded with ties going down
+        -floor_div(-(2 * self.depth * self.end.num - self.end.den), 2 * self.end.den)
+    }
+
+    // Floor tiles only count as seen if they're centred inside the row's slopes, which is what
+    // keeps things symmetric
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num
+            && col * self.end.den <= self.depth * self.end.num
+    }
+
+    fn next(&self) -> Row {
+        Row { depth: self.depth + 1, start: self.start, end: self.end }
+    }
+}
+
+fn floor_div(n: i32, d: i32) -> i32 {
+    if n >= 0 { n / d } else { -((-n + d - 1) / d) }
+}
+
+fn slope(depth: i32, col: i32) -> Slope {
+    Slope { num: 2 * col - 1, den: 2 * depth }
+}
+
+/// Calls `reveal` on every position within `radius` of `origin` that can be seen from it. Walls
+/// and anything else `blocks` returns true for get revealed, but can't be seen past.
+pub fn field_of_view<B, R>(origin: Position, radius: i32, blocks: B, mut reveal: R)
+    where B: Fn(Position) -> bool, R: FnMut(Position)
+{
+    reveal(origin);
+    for quadrant in 0..4 {
+        let row = Row {
+            depth: 1,
+            start: Slope { num: -1, den: 1 },
+            end: Slope { num: 1, den: 1 },
+        };
+        scan(origin, quadrant, radius, row, &blocks, &mut reveal);
+    }
+}
+
+fn scan<B, R>(origin: Position, quadrant: u8, radius: i32, mut row: Row, blocks: &B, reveal: &mut R)
+    where B: Fn(Position) -> bool, R: FnMut(Position)
+{
+    if row.depth > radius {
+        return;
+    }
+    let depth = row.depth;
+    let transform = |col: i32| match quadrant {
+        0 => Position { x: origin.x + col, y: origin.y - depth },
+        1 => Position { x: origin.x + col, y: origin.y + depth },
+        2 => Position { x: origin.x + depth, y: origin.y + col },
+        _ => Position { x: origin.x - depth, y: origin.y + col },
+    };
+    let mut prev_blocked = None;
+    let mut pending = Vec::new();
+    for col in row.min_col()..row.max_col() + 1 {
+        let pos = transform(col);
+        let blocked = blocks(pos);
+        let in_range = depth * depth + col * col <= radius * radius + radius;
+        if in_range && (blocked || row.is_symmetric(col)) {
+            reveal(pos);
+        }
+        if prev_blocked == Some(true) && !blocked {
+            row.start = slope(depth, col);
+        }
+        if prev_blocked == Some(false) && blocked {
+            let mut next = row.next();
+            next.end = slope(depth, col);
+            pending.push(next);
+        }
+        prev_blocked = Some(blocked);
+    }
+    if prev_blocked == Some(false) {
+        pending.push(row.next());
+    }
+    for next in pending {
+        scan(origin, quadrant, radius, next, blocks, reveal);
+    }
+}
@@ -1,92 +1,668 @@
+use std::collections::VecDeque;
+
 use super::*;
+use super::templates::{room_templates, RoomKind, RoomTemplate};
 
 use rand::Rng;
 
-const ROOM_XS: &[i32] = &[3, 9, 15, 21, 27, 33];
-const ROOM_YS: &[i32] = &[3, 9, 15, 21];
+// Room lattice spacing. Maps are always a whole number of cells across (plus the outer wall), so
+// every generator can lay out a level of any size.
+const CELL_SIZE: i32 = 6;
 
-// TODO: real level types
-pub enum LevelType {
-    Test,
+// Levels start out as a 6x4 lattice and grow the deeper the campaign goes
+fn level_size(depth: i32) -> (i32, i32) {
+    let depth = depth.max(0);
+    let cols = (6 + depth / 2).min(10);
+    let rows = (4 + depth / 3).min(7);
+    (cols * CELL_SIZE + 1, rows * CELL_SIZE + 1)
+}
+
+// How the room lattice decides which neighbouring rooms get a hallway between them
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub struct LatticeOptions {
+    // Percent chance that each hallway left out of the spanning tree gets added back as a loop
+    pub loop_percent: i32,
+    // Without dead ends, any room with only one way in gets an extra hallway
+    pub dead_ends: bool,
+    // Percent chance that a hallway that didn't make the cut becomes a secret passage instead
+    pub secret_percent: i32,
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
-pub enum RoomStyle {
-    Square,
-    Rounded,
-    Diamond,
-    Hallway,
-    Plus,
-    RoundedPlus,
-    Corner,
-    Random,
-    ExitSquare,
-}
-
-pub fn generate_map(level: &mut Level, typ: LevelType) {
-    match typ {
-        LevelType::Test => { test_map(level); }
+pub enum LevelType {
+    // A lattice of 5x5 rooms joined up by hallways
+    Lattice(LatticeOptions),
+    // Binary space partitioned rooms joined up by corridors
+    Rooms,
+    // Cellular automata caves, like a chunk of corrupted memory
+    Caves,
+    // Rows of server racks with aisles between them
+    DataCenter,
+}
+
+struct Theme {
+    intro: &'static str,
+    level_type: LevelType,
+    // Dark levels only have this many pools of light
+    lights: Option<i32>,
+    // Relative odds of each kind of enemy showing up
+    enemies: &'static [(EntityType, i32)],
+}
+
+// One theme per level of the campaign, reusing the last one if the campaign runs long
+const THEMES: &[Theme] = &[
+    Theme {
+        intro: "You are in some sort of server. It seems pretty quiet here.",
+        // No dead ends to get cornered in on the first level
+        level_type: LevelType::Lattice(LatticeOptions {
+            loop_percent: 25,
+            dead_ends: false,
+            secret_percent: 30,
+        }),
+        lights: None,
+        enemies: &[(EntityType::Defender, 3), (EntityType::Hunter, 2), (EntityType::Reaper, 1)],
+    },
+    Theme {
+        intro: "You slip into a mail relay. Junk packets drift through the corridors.",
+        level_type: LevelType::Rooms,
+        lights: None,
+        enemies: &[(EntityType::Defender, 2), (EntityType::Hunter, 3), (EntityType::Reaper, 1)],
+    },
+    Theme {
+        intro: "A database cluster, packed with records. Something is guarding them.",
+        level_type: LevelType::DataCenter,
+        lights: None,
+        enemies: &[(EntityType::Defender, 4), (EntityType::Hunter, 1), (EntityType::Reaper, 1)],
+    },
+    Theme {
+        intro: "A botnet hub. Whatever compromised this server has teeth.",
+        level_type: LevelType::Caves,
+        lights: Some(8),
+        enemies: &[(EntityType::Defender, 1), (EntityType::Hunter, 4), (EntityType::Reaper, 2)],
+    },
+    Theme {
+        intro: "An old backup archive. Half-deleted processes linger in the dark.",
+        level_type: LevelType::Lattice(LatticeOptions {
+            loop_percent: 10,
+            dead_ends: true,
+            secret_percent: 60,
+        }),
+        lights: Some(5),
+        enemies: &[(EntityType::Defender, 2), (EntityType::Hunter, 1), (EntityType::Reaper, 3)],
+    },
+    Theme {
+        intro: "The authentication server. Every process here wants to check your credentials.",
+        level_type: LevelType::Rooms,
+        lights: None,
+        enemies: &[(EntityType::Defender, 3), (EntityType::Hunter, 3), (EntityType::Reaper, 2)],
+    },
+    Theme {
+        intro: "The inner firewall. Nothing gets any further without a fight.",
+        level_type: LevelType::DataCenter,
+        lights: None,
+        enemies: &[(EntityType::Defender, 3), (EntityType::Hunter, 2), (EntityType::Reaper, 3)],
+    },
+];
+
+const CORE_THEME: Theme = Theme {
+    intro: "You have reached the mainframe. Its core pulses somewhere ahead. Delete it.",
+    level_type: LevelType::Rooms,
+    lights: None,
+    enemies: &[(EntityType::Defender, 2), (EntityType::Hunter, 2), (EntityType::Reaper, 2)],
+};
+
+// Spawn markers from room templates, with None meaning whatever the level's theme picks
+type Spawns = Vec<(Position, Option<EntityType>)>;
+
+pub fn generate_level(level: &mut Level) {
+    let theme = if level.is_final() {
+        &CORE_THEME
+    } else {
+        &THEMES[(level.level.max(0) as usize).min(THEMES.len() - 1)]
     };
+    let (width, height) = level_size(level.level);
+    level.map = Grid::new(width, height);
+    let mut spawns = Vec::new();
+    let exit = generate_map(level, theme.level_type, &mut spawns);
+    if level.is_final() {
+        level.set_tile(exit, Tile::Floor);
+        place_entity(level, exit, EntityType::Core);
+    }
+    if let Some(lights) = theme.lights {
+        light_map(level, exit, lights);
+    }
+    for (pos, t) in spawns {
+        if !near_player(level, pos) {
+            let t = t.unwrap_or_else(|| pick_enemy(level, theme));
+            place_entity(level, pos, t);
+        }
+    }
+    populate(level, theme);
+    level.log(LogEvent::LevelIntro(String::from(theme.intro)), &[]);
 }
 
-fn test_map(level: &mut Level) {
-    for &y in ROOM_YS {
-        for &x in ROOM_XS {
-            let style = if x == 3 && y == 3 {
-                RoomStyle::Square
-            } else if x == 33 && y == 21 {
-                RoomStyle::ExitSquare
-            } else {
-                RoomStyle::Random
-            };
-            place_room(level, Position { x, y }, style);
-            // TODO: real passageway handling
-            if x > 3 {
-                level.set_tile(Position { x: x - 3, y }, Tile::Door);
-            }
-            if y > 3 {
-                level.set_tile(Position { x, y: y - 3 }, Tile::Door);
+// Lays out the map and places the player, returning the position of the exit
+fn generate_map(level: &mut Level, typ: LevelType, spawns: &mut Spawns) -> Position {
+    let (start, exit) = match typ {
+        LevelType::Lattice(options) => lattice_map(level, options, spawns),
+        LevelType::Rooms => bsp_map(level, spawns),
+        LevelType::Caves => cave_map(level),
+        LevelType::DataCenter => datacenter_map(level),
+    };
+    // Room templates can put a wall anywhere, including right where the player is supposed to go
+    if level.get_sq(start).tile.is_solid() {
+        level.set_tile(start, Tile::Floor);
+    }
+    // The generators should all manage this by themselves, but an ugly tunnel beats a stuck player
+    if ai::distance(&distances(level, start), exit).is_none() {
+        dig_tunnel(level, start, exit);
+    }
+    level.set_tile(exit, Tile::Exit);
+    level.move_entity(PLAYER, start);
+    exit
+}
+
+// Plunges the level into darkness, apart from the entrance, the exit and a few pools of light
+fn light_map(level: &mut Level, exit: Position, lights: i32) {
+    const LIGHT_RADIUS: i32 = 4;
+    for sq in level.map.squares.iter_mut() {
+        sq.lit = false;
+    }
+    let mut sources = vec![exit];
+    sources.extend(level.positions.get(&PLAYER).cloned());
+    for _ in 0..lights {
+        let pos = Position {
+            x: level.rng.gen_range(1, level.width() - 1),
+            y: level.rng.gen_range(1, level.height() - 1),
+        };
+        if !level.get_sq(pos).tile.is_solid() {
+            sources.push(pos);
+        }
+    }
+    for source in sources {
+        let mut lit = Vec::new();
+        fov::field_of_view(source, LIGHT_RADIUS, |pos| level.blocks_sight(pos), |pos| lit.push(pos));
+        for pos in lit {
+            if let Some(sq) = level.map.get_mut(pos) {
+                sq.lit = true;
             }
         }
     }
-    level.set_tile(Position { x: 33, y: 21 }, Tile::Exit);
-    level.move_entity(PLAYER, Position { x: 3, y: 3 });
-    for _ in 0..64 {
+}
+
+fn populate(level: &mut Level, theme: &Theme) {
+    // Scaled to the size of the map, so bigger levels aren't emptier
+    let (base_width, base_height) = level_size(0);
+    let area = level.width() * level.height();
+    let attempts = (16 + 8 * level.level).min(96) * area / (base_width * base_height);
+    for _ in 0..attempts {
         let pos = Position {
-            x: level.rng.gen_range(1, 35),
-            y: level.rng.gen_range(1, 23),
+            x: level.rng.gen_range(1, level.width() - 1),
+            y: level.rng.gen_range(1, level.height() - 1),
         };
-        if level.is_open(pos) && level.get_sq(pos).tile != Tile::Door {
-            place_entity(level, pos, EntityType::UnknownThing);
+        if !near_player(level, pos) && level.is_open(pos) && level.get_sq(pos).tile != Tile::Door {
+            let t = pick_enemy(level, theme);
+            place_entity(level, pos, t);
         }
     }
-    level.log.messages.push(String::from("You are in some sort of server. It seems pretty quiet here."));
 }
 
+fn pick_enemy(level: &mut Level, theme: &Theme) -> EntityType {
+    let total: i32 = theme.enemies.iter().map(|&(_, weight)| weight).sum();
+    let mut roll = level.rng.gen_range(0, total);
+    let mut index = 0;
+    while roll >= theme.enemies[index].1 {
+        roll -= theme.enemies[index].1;
+        index += 1;
+    }
+    theme.enemies[index].0
+}
+
+// Don't drop anything right on top of the player
+fn near_player(level: &Level, pos: Position) -> bool {
+    level.positions.get(&PLAYER)
+        .map(|&start| (pos.x - start.x).abs() + (pos.y - start.y).abs() <= 3)
+        .unwrap_or(false)
+}
+
+fn lattice_map(level: &mut Level, options: LatticeOptions, spawns: &mut Spawns) -> (Position, Position) {
+    let cols = ((level.width() - 1) / CELL_SIZE) as usize;
+    let rows = ((level.height() - 1) / CELL_SIZE) as usize;
+    let room_pos = |i: usize| Position {
+        x: CELL_SIZE / 2 + CELL_SIZE * (i % cols) as i32,
+        y: CELL_SIZE / 2 + CELL_SIZE * (i / cols) as i32,
+    };
+    let (start, exit) = (0, cols * rows - 1);
+    for i in 0..cols * rows {
+        let kind = if i == start {
+            RoomKind::Start
+        } else if i == exit {
+            RoomKind::Exit
+        } else {
+            RoomKind::Room
+        };
+        let pos = room_pos(i);
+        let corner = Position { x: pos.x - 2, y: pos.y - 2 };
+        match pick_template(level, kind, |w, h| w == 5 && h == 5) {
+            Some((template, rotation)) => {
+                place_room(level, &template, rotation, corner, spawns);
+            }
+            // An empty room will do if there's no suitable template
+            None => for y in 0..5 {
+                for x in 0..5 {
+                    level.set_tile(Position { x: corner.x + x, y: corner.y + y }, Tile::Floor);
+                }
+            },
+        }
+    }
+
+    // Every pair of neighbouring rooms could have a hallway between them
+    let mut hallways = Vec::new();
+    for i in 0..cols * rows {
+        if i % cols + 1 < cols {
+            hallways.push((i, i + 1));
+        }
+        if i / cols + 1 < rows {
+            hallways.push((i, i + cols));
+        }
+    }
+    level.rng.shuffle(&mut hallways);
+
+    // Randomised Kruskal's: a hallway joins the spanning tree if it links up two separate groups
+    // of rooms. Everything else gets pruned, unless it's picked to make a loop.
+    let mut groups: Vec<usize> = (0..cols * rows).collect();
+    let mut exits = vec![0; cols * rows];
+    let mut pruned = Vec::new();
+    for &(a, b) in &hallways {
+        let (ga, gb) = (find_group(&mut groups, a), find_group(&mut groups, b));
+        if ga != gb {
+            groups[ga] = gb;
+            exits[a] += 1;
+            exits[b] += 1;
+            carve_hallway(level, room_pos(a), room_pos(b), Tile::Door);
+        } else {
+            pruned.push((a, b));
+        }
+    }
+    for (a, b) in pruned {
+        let dead_end = exits[a] == 1 || exits[b] == 1;
+        if level.rng.gen_range(0, 100) < options.loop_percent || !options.dead_ends && dead_end {
+            exits[a] += 1;
+            exits[b] += 1;
+            carve_hallway(level, room_pos(a), room_pos(b), Tile::Door);
+        } else if level.rng.gen_range(0, 100) < options.secret_percent {
+            carve_hallway(level, room_pos(a), room_pos(b), Tile::Secret);
+        }
+    }
+
+    // The spanning tree reaches every room, and generate_map flood fills to double check
+    (room_pos(start), room_pos(exit))
+}
+
+fn find_group(groups: &mut [usize], room: usize) -> usize {
+    let mut root = room;
+    while groups[root] != root {
+        root = groups[root];
+    }
+    groups[room] = root;
+    root
+}
+
+// Neighbouring rooms only have a single wall between them, halfway across
+fn carve_hallway(level: &mut Level, a: Position, b: Position, tile: Tile) {
+    level.set_tile(Position { x: (a.x + b.x) / 2, y: (a.y + b.y) / 2 }, tile);
+}
+
+#[derive(Copy, Clone)]
+struct Rect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Rect {
+    fn center(&self) -> Position {
+        Position { x: self.x + self.w / 2, y: self.y + self.h / 2 }
+    }
+}
+
+fn bsp_map(level: &mut Level, spawns: &mut Spawns) -> (Position, Position) {
+    let mut rooms = Vec::new();
+    let bounds = Rect { x: 0, y: 0, w: level.width(), h: level.height() };
+    bsp_split(level, bounds, &mut rooms, spawns);
+
+    // Doors go wherever a corridor squeezes through the wall around a room
+    for room in &rooms {
+        let mut ring = Vec::new();
+        for x in room.x - 1..room.x + room.w + 1 {
+            ring.push(Position { x, y: room.y - 1 });
+            ring.push(Position { x, y: room.y + room.h });
+        }
+        for y in room.y..room.y + room.h {
+            ring.push(Position { x: room.x - 1, y });
+            ring.push(Position { x: room.x + room.w, y });
+        }
+        for pos in ring {
+            if level.get_sq(pos).tile == Tile::Floor && is_chokepoint(level, pos) {
+                level.set_tile(pos, Tile::Door);
+            }
+        }
+    }
+
+    let start = rooms[0].center();
+    let exit = farthest(&distances(level, start));
+    (start, exit)
+}
+
+// Splits the area in two until the pieces get too small to hold more than one room, then joins
+// the halves back up with a corridor. Returns the centre of one of the rooms inside.
+fn bsp_split(level: &mut Level, area: Rect, rooms: &mut Vec<Rect>, spawns: &mut Spawns) -> Position {
+    const MIN_SIZE: i32 = 7;
+    let (can_split_x, can_split_y) = (area.w >= 2 * MIN_SIZE, area.h >= 2 * MIN_SIZE);
+    if !can_split_x && !can_split_y {
+        // Leave at least one wall between the room and the edge of its area
+        let (max_w, max_h) = (area.w - 2, area.h - 2);
+        let template = if level.rng.gen() {
+            pick_template(level, RoomKind::Room, |w, h| w <= max_w && h <= max_h)
+        } else {
+            None
+        };
+        let (w, h) = match template {
+            Some((ref template, rotation)) => template.size(rotation),
+            None => (level.rng.gen_range(3, area.w - 1), level.rng.gen_range(3, area.h - 1)),
+        };
+        let room = Rect {
+            x: area.x + 1 + level.rng.gen_range(0, area.w - 1 - w),
+            y: area.y + 1 + level.rng.gen_range(0, area.h - 1 - h),
+            w,
+            h,
+        };
+        match template {
+            Some((template, rotation)) => {
+                place_room(level, &template, rotation, Position { x: room.x, y: room.y }, spawns);
+            }
+            None => for y in room.y..room.y + room.h {
+                for x in room.x..room.x + room.w {
+                    level.set_tile(Position { x, y }, Tile::Floor);
+                }
+            },
+        }
+        rooms.push(room);
+        return room.center();
+    }
+
+    let split_x = if can_split_x && can_split_y {
+        level.rng.gen_range(0, area.w + area.h) < area.w
+    } else {
+        can_split_x
+    };
+    let (first, second) = if split_x {
+        let w = level.rng.gen_range(MIN_SIZE, area.w - MIN_SIZE + 1);
+        (Rect { w, ..area }, Rect { x: area.x + w, w: area.w - w, ..area })
+    } else {
+        let h = level.rng.gen_range(MIN_SIZE, area.h - MIN_SIZE + 1);
+        (Rect { h, ..area }, Rect { y: area.y + h, h: area.h - h, ..area })
+    };
+    let a = bsp_split(level, first, rooms, spawns);
+    let b = bsp_split(level, second, rooms, spawns);
+    dig_tunnel(level, a, b);
+    if level.rng.gen() { a } else { b }
+}
+
+fn cave_map(level: &mut Level) -> (Position, Position) {
+    let (w, h) = (level.width() as usize, level.height() as usize);
+    loop {
+        let mut open = vec![vec![false; w]; h];
+        for row in open.iter_mut().take(h - 1).skip(1) {
+            for cell in row.iter_mut().take(w - 1).skip(1) {
+                *cell = level.rng.gen_range(0, 100) >= 45;
+            }
+        }
+        // Smooth out the noise: anything mostly surrounded by wall becomes wall and vice versa
+        for _ in 0..4 {
+            let mut next = vec![vec![false; w]; h];
+            for (y, row) in next.iter_mut().enumerate().take(h - 1).skip(1) {
+                for (x, cell) in row.iter_mut().enumerate().take(w - 1).skip(1) {
+                    let walls = open[y - 1..y + 2].iter()
+                        .flat_map(|r| &r[x - 1..x + 2])
+                        .filter(|&&o| !o)
+                        .count();
+                    *cell = walls < 5;
+                }
+            }
+            open = next;
+        }
+        for (y, row) in open.iter().enumerate() {
+            for (x, &o) in row.iter().enumerate() {
+                let tile = if o { Tile::Floor } else { Tile::Wall };
+                level.set_tile(Position { x: x as i32, y: y as i32 }, tile);
+            }
+        }
+
+        // Only keep the biggest cave, and try again if it's too cramped to be worth playing
+        let mut region = vec![vec![false; w]; h];
+        let mut best: Vec<Position> = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                if !open[y][x] || region[y][x] {
+                    continue;
+                }
+                let dist = distances(level, Position { x: x as i32, y: y as i32 });
+                let mut cells = Vec::new();
+                for cy in 0..h {
+                    for cx in 0..w {
+                        if dist[cy][cx].is_some() {
+                            region[cy][cx] = true;
+                            cells.push(Position { x: cx as i32, y: cy as i32 });
+                        }
+                    }
+                }
+                if cells.len() > best.len() {
+                    best = cells;
+                }
+            }
+        }
+        if best.len() < w * h / 3 {
+            continue;
+        }
+        let dist = distances(level, best[0]);
+        for (y, row) in dist.iter().enumerate() {
+            for (x, d) in row.iter().enumerate() {
+                if d.is_none() {
+                    level.set_tile(Position { x: x as i32, y: y as i32 }, Tile::Wall);
+                }
+            }
+        }
+
+        let start = *level.rng.choose(&best).unwrap();
+        let exit = farthest(&distances(level, start));
+        return (start, exit);
+    }
+}
+
+fn datacenter_map(level: &mut Level) -> (Position, Position) {
+    let (w, h) = (level.width(), level.height());
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            level.set_tile(Position { x, y }, Tile::Floor);
+        }
+    }
+    // Racks run in rows, leaving a two wide aisle around the outside so everything stays connected.
+    // Single racks sometimes get a door through the gap instead of an open cross aisle.
+    let mut y = 3;
+    loop {
+        let depth = level.rng.gen_range(1, 3);
+        if y + depth > h - 3 {
+            break;
+        }
+        let mut x = 3;
+        while x < w - 3 {
+            let run = level.rng.gen_range(3, 8);
+            for rx in x..(x + run).min(w - 3) {
+                for ry in y..y + depth {
+                    level.set_tile(Position { x: rx, y: ry }, Tile::Wall);
+                }
+            }
+            x += run;
+            let gap = Position { x, y };
+            if depth == 1 && x < w - 4 && level.rng.gen_range(0, 3) == 0 {
+                level.set_tile(gap, Tile::Door);
+            }
+            x += level.rng.gen_range(1, 3);
+        }
+        y += depth + level.rng.gen_range(2, 4);
+    }
+
+    let start = Position { x: 1, y: level.rng.gen_range(1, h - 1) };
+    let exit = Position { x: w - 2, y: level.rng.gen_range(1, h - 1) };
+    (start, exit)
+}
+
+fn is_chokepoint(level: &Level, pos: Position) -> bool {
+    let wall = |dir| level.get_sq(pos.step(dir)).tile == Tile::Wall;
+    wall(Direction::Left) && wall(Direction::Right) || wall(Direction::Up) && wall(Direction::Down)
+}
+
+// Walking distances from the start over anything that isn't a wall
+fn distances(level: &Level, start: Position) -> Vec<Vec<Option<i32>>> {
+    let mut dist = vec![vec![None; level.width() as usize]; level.height() as usize];
+    let mut pending = VecDeque::new();
+    dist[start.y as usize][start.x as usize] = Some(0);
+    pending.push_back(start);
+    while let Some(pos) = pending.pop_front() {
+        let d = dist[pos.y as usize][pos.x as usize].unwrap();
+        for &dir in DIRECTIONS {
+            let next = pos.step(dir);
+            if level.get_sq(next).tile.is_solid() {
+                continue;
+            }
+            let cell = &mut dist[next.y as usize][next.x as usize];
+            if cell.is_none() {
+                *cell = Some(d + 1);
+                pending.push_back(next);
+            }
+        }
+    }
+    dist
+}
+
+fn farthest(dist: &[Vec<Option<i32>>]) -> Position {
+    let mut best = (0, Position { x: 0, y: 0 });
+    for (y, row) in dist.iter().enumerate() {
+        for (x, &d) in row.iter().enumerate() {
+            if let Some(d) = d {
+                if d > best.0 {
+                    best = (d, Position { x: x as i32, y: y as i32 });
+                }
+            }
+        }
+    }
+    best.1
+}
+
+// Carves an L-shaped corridor through any walls between the two positions
+fn dig_tunnel(level: &mut Level, from: Position, to: Position) {
+    let corner = if level.rng.gen() {
+        Position { x: to.x, y: from.y }
+    } else {
+        Position { x: from.x, y: to.y }
+    };
+    for &(a, b) in &[(from, corner), (corner, to)] {
+        let mut pos = a;
+        loop {
+            if level.get_sq(pos).tile == Tile::Wall {
+                level.set_tile(pos, Tile::Floor);
+            }
+            if pos == b {
+                break;
+            }
+            pos = Position { x: pos.x + (b.x - pos.x).signum(), y: pos.y + (b.y - pos.y).signum() };
+        }
+    }
+}
+
+// Offers three different cards, favouring whatever the player has been absorbing and getting
+// nastier the deeper they go
+pub fn generate_mutations(
+    rng: &mut GameRng, collected: &BTreeMap<Card, i32>, depth: i32,
+) -> [Card; 3] {
+    let mut pool = vec![
+        (Card::Attack(1), 4),
+        (Card::Defend(2), 4),
+        (Card::Block, 3),
+        (Card::Strike, 2),
+        (Card::Dodge, 2),
+        (Card::Push, 2),
+        (Card::Shield, 2),
+        (Card::Overclock, 2),
+        (Card::Slow(1), 1 + depth),
+        (Card::Virus(2), depth),
+        (Card::Kill(1), depth),
+        (Card::Attack(2), depth),
+    ];
+    for (&card, &count) in collected {
+        match pool.iter().position(|&(c, _)| c == card) {
+            Some(i) => { pool[i].1 += 3 * count; }
+            None => { pool.push((card, 3 * count)); }
+        }
+    }
+    pool.retain(|&(_, weight)| weight > 0);
+
+    let mut offers = [Card::Attack(1); 3];
+    for offer in offers.iter_mut() {
+        let total: i32 = pool.iter().map(|&(_, weight)| weight).sum();
+        let mut roll = rng.gen_range(0, total);
+        let mut index = 0;
+        while roll >= pool[index].1 {
+            roll -= pool[index].1;
+            index += 1;
+        }
+        *offer = pool.remove(index).0;
+    }
+    offers
+}
+
+fn spawn_with_deck(level: &mut Level, t: EntityType, pos: Position, deck: Vec<Card>) {
+    if let Some(e) = level.spawn_entity(t, pos) {
+        level.decks.insert(e, deck.into_iter().map(
+            |c| CardState { card: c, status: CardStatus::Active }
+        ).collect());
+    }
+}
+
+// Enemy decks get beefier the deeper into the campaign the player gets
 fn place_entity(level: &mut Level, pos: Position, t: EntityType) {
+    let depth = level.level;
     match t {
         EntityType::Player => { /* TODO: handle this? */ }
         EntityType::Defender => {
-            let defender = level.spawn_entity(EntityType::Defender, pos);
-            defender.map(|d| level.decks.insert(d, vec![
-                CardState{ card: Card::Defend(2), status: CardStatus::Active },
-                CardState{ card: Card::Block, status: CardStatus::Active },
-                CardState{ card: Card::Push, status: CardStatus::Active },
-            ]));
+            let mut deck = vec![Card::Defend(2 + depth / 3), Card::Block, Card::Push, Card::Shield];
+            if depth >= 4 {
+                deck.push(Card::Block);
+            }
+            spawn_with_deck(level, t, pos, deck);
         }
         EntityType::Hunter => {
-            let hunter = level.spawn_entity(EntityType::Hunter, pos);
-            hunter.map(|h| level.decks.insert(h, vec![
-                CardState{ card: Card::Strike, status: CardStatus::Active },
-                CardState{ card: Card::Dodge, status: CardStatus::Active },
-            ]));
+            let mut deck = vec![Card::Strike, Card::Dodge];
+            if depth >= 2 {
+                deck.push(Card::Attack(1));
+            }
+            if depth >= 5 {
+                deck.push(Card::Slow(1));
+            }
+            spawn_with_deck(level, t, pos, deck);
         }
         EntityType::Reaper => {
-            let reaper = level.spawn_entity(EntityType::Reaper, pos);
-            reaper.map(|r| level.decks.insert(r, vec![
-                CardState{ card: Card::Kill(1), status: CardStatus::Active },
-                CardState{ card: Card::Attack(1), status: CardStatus::Active },
-            ]));
+            let deck = vec![Card::Kill(1 + depth / 4), Card::Attack(1), Card::Virus(2)];
+            spawn_with_deck(level, t, pos, deck);
+        }
+        EntityType::Core => {
+            let deck = vec![
+                Card::Defend(2), Card::Defend(3), Card::Block, Card::Block, Card::Block,
+                Card::Shield, Card::Push, Card::Dodge,
+            ];
+            spawn_with_deck(level, t, pos, deck);
         }
         EntityType::UnknownThing => {
             let t = *level.rng.choose(&[
@@ -99,86 +675,63 @@ fn place_entity(level: &mut Level, pos: Position, t: EntityType) {
     }
 }
 
-// TODO: optional hallways that get pruned post level generation
-fn place_room(level: &mut Level, pos: Position, style: RoomStyle) {
-    let pattern = match style {
-        RoomStyle::Random => {
-            let style = *level.rng.choose(&[
-                RoomStyle::Square,
-                RoomStyle::Rounded,
-                RoomStyle::Diamond,
-                RoomStyle::Hallway,
-                RoomStyle::Plus,
-                RoomStyle::RoundedPlus,
-                RoomStyle::Corner,
-            ]).unwrap();
-            place_room(level, pos, style);
-            return;
-        }
-        RoomStyle::Square => b"\
-.....\
-.....\
-.....\
-.....\
-.....",
-        RoomStyle::Rounded => b"\
-#...#\
-.....\
-.....\
-.....\
-#...#",
-        RoomStyle::Diamond => b"\
-##.##\
-#...#\
-.....\
-#...#\
-##.##",
-        RoomStyle::Hallway => b"\
-##.##\
-##.##\
-.....\
-##.##\
-##.##",
-        RoomStyle::Plus => b"\
-.....\
-..#..\
-.###.\
-..#..\
-.....",
-        RoomStyle::RoundedPlus => b"\
-#...#\
-..#..\
-.###.\
-..#..\
-#...#",
-        RoomStyle::Corner => b"\
-##..#\
-##..#\
-.....\
-....#\
-##.##",
-        RoomStyle::ExitSquare => b"\
-.....\
-.....\
-..>..\
-.....\
-.....",
-    };
+// Picks a template of the given kind for the current depth, along with a rotation that makes it fit
+fn pick_template<F: Fn(i32, i32) -> bool>(
+    level: &mut Level, kind: RoomKind, fits: F,
+) -> Option<(RoomTemplate, usize)> {
+    let templates = room_templates();
+    let candidates: Vec<(&RoomTemplate, Vec<usize>)> = templates.iter()
+        .filter(|t| t.kind == kind && t.allowed_at(level.level))
+        .map(|t| (t, t.rotations.iter().cloned().filter(|&r| {
+            let (w, h) = t.size(r);
+            fits(w, h)
+        }).collect::<Vec<_>>()))
+        .filter(|(_, rotations)| !rotations.is_empty())
+        .collect();
+    let total: i32 = candidates.iter().map(|&(t, _)| t.weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = level.rng.gen_range(0, total);
+    for (template, rotations) in candidates {
+        if roll < template.weight {
+            let rotation = *level.rng.choose(&rotations).unwrap();
+            return Some((template.clone(), rotation));
+        }
+        roll -= template.weight;
+    }
+    None
+}
 
-    let rotation = level.rng.gen_range(0, 8);
-    for y in 0..5 {
-        for x in 0..5 {
-            let index = [
-                y*5 + x, y*5 + (4 - x), (4 - y)*5 + x, (4 - y)*5 + (4 - x),
-                x*5 + y, x*5 + (4 - y), (4 - x)*5 + y, (4 - x)*5 + (4 - y),
-            ][rotation] as usize;
-            let tile = match pattern[index] {
-                b'.' => Tile::Floor,
+// Copies the template onto the map with its top left corner at the given position, noting down
+// anything that should get spawned there once the map is done
+fn place_room(
+    level: &mut Level, template: &RoomTemplate, rotation: usize, corner: Position,
+    spawns: &mut Spawns,
+) {
+    let (w, h) = template.size(rotation);
+    for y in 0..h {
+        for x in 0..w {
+            let pos = Position { x: corner.x + x, y: corner.y + y };
+            let spawn = match template.cell(rotation, x, y) {
+                b' ' => { continue; }
+                b'E' => Some(None),
+                b'D' => Some(Some(EntityType::Defender)),
+                b'H' => Some(Some(EntityType::Hunter)),
+                b'R' => Some(Some(EntityType::Reaper)),
+                _ => None,
+            };
+            let tile = match template.cell(rotation, x, y) {
+                b'#' => Tile::Wall,
                 b'+' => Tile::Door,
                 b'>' => Tile::Exit,
-                _ => Tile::Wall,
+                b's' => Tile::Secret,
+                _ => Tile::Floor,
             };
-            level.set_tile(Position { x: pos.x-2+x, y: pos.y-2+y }, tile);
+            level.set_tile(pos, tile);
+            if let Some(t) = spawn {
+                spawns.push((pos, t));
+            }
         }
     }
 }
@@ -1,11 +1,29 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
 
-use rand::{Rng, SeedableRng, StdRng};
+use rand::Rng;
 
+mod ai;
+mod fov;
 mod gen;
+mod replay;
+mod rng;
+mod save;
+mod tactics;
+mod templates;
+mod text;
 
-pub const MAP_WIDTH: usize = 37;
-pub const MAP_HEIGHT: usize = 25;
+pub use self::replay::{Input, Recorder, Replay};
+pub use self::templates::load_room_templates;
+pub use self::text::{a_or_an, capitalize};
+use self::rng::GameRng;
+
+pub const DEFAULT_CAMPAIGN_LENGTH: i32 = 8;
+
+const DIRECTIONS: &[Direction] = &[Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+// Unlit squares can only be made out from right next to them
+const DARK_SIGHT_RADIUS: i32 = 1;
 
 #[derive(Eq, PartialEq, Copy, Clone, Ord, PartialOrd)]
 struct Entity(u64);
@@ -18,23 +36,34 @@ pub struct Position {
     pub y: i32,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum Tile {
     Floor,
     Wall,
     Door,
     Exit,
+    // A hidden door that looks like a wall until the player walks into it
+    Secret,
 }
 
 impl Tile {
     fn render(self) -> char {
         match self {
             Tile::Floor => '.',
-            Tile::Wall => '#',
+            Tile::Wall | Tile::Secret => '#',
             Tile::Door => '+',
             Tile::Exit => '>',
         }
     }
+
+    fn is_solid(self) -> bool {
+        self == Tile::Wall || self == Tile::Secret
+    }
+
+    // What the player gets to see
+    fn apparent(self) -> Tile {
+        if self == Tile::Secret { Tile::Wall } else { self }
+    }
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
@@ -48,12 +77,65 @@ enum Visibility {
 struct Square {
     tile: Tile,
     entity: Option<Entity>,
+    fragment: Option<Card>,
     visibility: Visibility,
+    lit: bool,
 }
 
 impl Square {
     fn is_open(self) -> bool {
-        self.entity.is_none() && self.tile != Tile::Wall
+        self.entity.is_none() && !self.tile.is_solid()
+    }
+}
+
+// Squares are stored row by row. Every level picks its own size, so all lookups are checked
+// against the bounds.
+#[derive(Clone)]
+struct Grid {
+    width: i32,
+    height: i32,
+    squares: Vec<Square>,
+}
+
+impl Grid {
+    // A solid block of unexplored wall
+    fn new(width: i32, height: i32) -> Grid {
+        let (width, height) = (width.max(0), height.max(0));
+        Grid {
+            width,
+            height,
+            squares: vec![Square {
+                tile: Tile::Wall,
+                entity: None,
+                fragment: None,
+                visibility: Visibility::Unknown,
+                lit: true,
+            }; (width * height) as usize],
+        }
+    }
+
+    fn contains(&self, pos: Position) -> bool {
+        0 <= pos.x && pos.x < self.width && 0 <= pos.y && pos.y < self.height
+    }
+
+    fn get(&self, pos: Position) -> Option<&Square> {
+        if self.contains(pos) {
+            self.squares.get((pos.y * self.width + pos.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn get_mut(&mut self, pos: Position) -> Option<&mut Square> {
+        if self.contains(pos) {
+            self.squares.get_mut((pos.y * self.width + pos.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn rows<'a>(&'a self) -> ::std::slice::Chunks<'a, Square> {
+        self.squares.chunks(self.width.max(1) as usize)
     }
 }
 
@@ -64,16 +146,43 @@ pub enum EntityType {
     Defender,
     Hunter,
     Reaper,
+    Core,
 }
 
 impl EntityType {
-    fn render(self) -> char {
+    pub fn render(self) -> char {
         match self {
             EntityType::UnknownThing => '?',
             EntityType::Player => '@',
             EntityType::Defender => 'd',
             EntityType::Hunter => 'h',
             EntityType::Reaper => 'r',
+            EntityType::Core => 'C',
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            EntityType::UnknownThing => "Something you can't make out.",
+            EntityType::Player => "You, a program eating its way through the network.",
+            EntityType::Defender => "Stays put until it spots you, then holds its ground. Likes to \
+                                     shove you into walls.",
+            EntityType::Hunter => "Patrols the level and keeps searching for you long after \
+                                   losing sight of you. Lunges from two squares away.",
+            EntityType::Reaper => "Hangs back out of reach until its Kill card is sure to finish \
+                                   you off.",
+            EntityType::Core => "What you came here to delete. Waits at the end of the last \
+                                 level.",
+        }
+    }
+
+    // How far away lit squares can be seen from
+    fn sight_radius(self) -> i32 {
+        match self {
+            EntityType::Player => 9,
+            EntityType::Hunter => 8,
+            EntityType::Defender | EntityType::Reaper => 6,
+            EntityType::Core | EntityType::UnknownThing => 0,
         }
     }
 }
@@ -81,12 +190,40 @@ impl EntityType {
 #[derive(Eq, PartialEq, Copy, Clone)]
 enum Goal {
     Move(Position),
-    // Attack(Entity),
-    // Wait,
-    // Rest,
+    Attack(Entity),
+    Wait,
+    Rest,
+}
+
+// What an enemy is generally up to, which decides the goals it picks from turn to turn
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+enum AiState {
+    // Stays put until the player shows up
+    Idle,
+    // Wanders around the level
+    Patrol,
+    // Heads to wherever it last saw the player
+    Investigate,
+    // Chases the player down
+    Hunt,
+    // Keeps away from the player while it recovers its cards
+    Flee,
+}
+
+// Where an enemy last saw the player, and how many turns ago that was
+#[derive(Eq, PartialEq, Copy, Clone)]
+struct Memory {
+    pos: Position,
+    age: i32,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+struct Mind {
+    state: AiState,
+    goal: Goal,
+    memory: Option<Memory>,
 }
 
-// TODO: real display functionality
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Debug)]
 pub enum Card {
     Attack(i32),
@@ -96,6 +233,91 @@ pub enum Card {
     Dodge,
     Block,
     Push,
+    Shield,
+    Slow(i32),
+    Virus(i32),
+    Overclock,
+}
+
+impl Card {
+    pub fn upgraded(self) -> Option<Card> {
+        match self {
+            Card::Attack(n) => Some(Card::Attack(n + 1)),
+            Card::Defend(n) => Some(Card::Defend(n + 1)),
+            Card::Kill(n) => Some(Card::Kill(n + 1)),
+            Card::Slow(n) => Some(Card::Slow(n + 1)),
+            Card::Virus(n) => Some(Card::Virus(n + 1)),
+            Card::Strike | Card::Dodge | Card::Block | Card::Push | Card::Shield | Card::Overclock => None,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Card::Attack(n) => format!("Attack {}", n),
+            Card::Defend(n) => format!("Defend {}", n),
+            Card::Kill(n) => format!("Kill {}", n),
+            Card::Strike => "Strike".to_string(),
+            Card::Dodge => "Dodge".to_string(),
+            Card::Block => "Block".to_string(),
+            Card::Push => "Push".to_string(),
+            Card::Shield => "Shield".to_string(),
+            Card::Slow(n) => format!("Slow {}", n),
+            Card::Virus(n) => format!("Virus {}", n),
+            Card::Overclock => "Overclock".to_string(),
+        }
+    }
+
+    /// When the card goes off. Only one card triggers per event, picked at random from the ones
+    /// that could.
+    pub fn trigger(self) -> String {
+        match self {
+            Card::Attack(_) => "When you attack.".to_string(),
+            Card::Defend(n) => format!("When you are hit for at least {} damage.", n),
+            Card::Kill(n) => format!(
+                "When you attack something whose hand and modifiers add up to less than the \
+                 damage plus {}.", n),
+            Card::Strike => "When you move towards something two squares away.".to_string(),
+            Card::Dodge => "When you are attacked from a direction and the square behind you \
+                            isn't a wall.".to_string(),
+            Card::Block => "When you are attacked.".to_string(),
+            Card::Push => "When you attack something that has no wall behind it.".to_string(),
+            Card::Shield => "When you rest.".to_string(),
+            Card::Slow(_) | Card::Virus(_) => "When you attack.".to_string(),
+            Card::Overclock => "When you wait.".to_string(),
+        }
+    }
+
+    pub fn effect(self) -> String {
+        match self {
+            Card::Attack(n) => format!("Deals {} extra damage.", n),
+            Card::Defend(n) => format!("Blocks {} damage.", n),
+            Card::Kill(n) => format!(
+                "Deals the damage plus {} straight to the target, skipping its own cards.", n),
+            Card::Strike => "Lunges forward and attacks.".to_string(),
+            Card::Dodge => "Steps back out of the way, or attacks whatever is standing \
+                            there.".to_string(),
+            Card::Block => "Cancels the attack.".to_string(),
+            Card::Push => "Shoves the target back a square and follows it, or slams it into \
+                           whatever is behind it.".to_string(),
+            Card::Shield => "Played on yourself: cancels the next attack against you.".to_string(),
+            Card::Slow(n) => format!("Played on the target: it loses its next {} turns.", n),
+            Card::Virus(n) => format!(
+                "Played on the target: it takes a point of damage on each of its next {} \
+                 turns.", n),
+            Card::Overclock => "Played on yourself: your next attack deals 2 extra \
+                                damage.".to_string(),
+        }
+    }
+
+    /// Whether triggering the card sends it straight to the discard pile. Cards that are played
+    /// as modifiers only get discarded once the modifier goes away.
+    pub fn discards_itself(self) -> bool {
+        match self {
+            Card::Defend(_) | Card::Dodge | Card::Block => true,
+            Card::Attack(_) | Card::Kill(_) | Card::Strike | Card::Push | Card::Shield
+            | Card::Slow(_) | Card::Virus(_) | Card::Overclock => false,
+        }
+    }
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
@@ -123,8 +345,20 @@ struct CardState {
     status: CardStatus,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
-enum Modifier {
+// Modifiers are attached to an entity by playing a card on it and count towards its health. When
+// a modifier goes away (expiring, being stripped by damage or its bearer dying) the source card
+// ends up back in the discard pile. Recovering the source card strips the modifier the same way,
+// but then puts the card straight back in hand.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Modifier {
+    // Cancels the next attack against the bearer
+    Shield,
+    // The bearer loses its next N turns
+    Slow(i32),
+    // The bearer takes a point of damage at the start of each of its next N turns
+    Virus(i32),
+    // The bearer's next attack deals 2 extra damage
+    Overclock,
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
@@ -144,94 +378,307 @@ enum CardOutcome {
     DiscardAndCancel,
 }
 
-struct MessageLog {
-    messages: Vec<String>,
+// The broad sort of thing a log entry records, for colouring and filtering
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum LogKind {
+    Attack,
+    Damage,
+    CardActivated,
+    CardDiscarded,
+    Kill,
+    LevelEvent,
+    System,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum LogEvent {
+    // Attacker, target and damage
+    Attack(EntityType, EntityType, i32),
+    // Source, victim and the damage that got through
+    Hit(EntityType, EntityType, i32),
+    // Owner, card and whether using it discarded it
+    CardActivated(EntityType, Card, bool),
+    // A modifier doing its thing to its bearer
+    ModifierActivated(EntityType, Modifier),
+    // Owner and the card knocked out of their hand by damage
+    CardDiscarded(EntityType, Card),
+    ModifierStripped(EntityType, Modifier),
+    // Killer and victim
+    Kill(EntityType, EntityType),
+    FragmentAbsorbed(Card),
+    HiddenPassage,
+    LevelIntro(String),
+    LevelExited,
+    CoreDeleted,
+    PlayerDeleted,
+    PressSpace,
+}
+
+impl LogEvent {
+    pub fn kind(&self) -> LogKind {
+        match *self {
+            LogEvent::Attack(..) => LogKind::Attack,
+            LogEvent::Hit(..) => LogKind::Damage,
+            LogEvent::CardActivated(..) | LogEvent::ModifierActivated(..) => LogKind::CardActivated,
+            LogEvent::CardDiscarded(..) | LogEvent::ModifierStripped(..) => LogKind::CardDiscarded,
+            LogEvent::Kill(..) => LogKind::Kill,
+            LogEvent::FragmentAbsorbed(_) | LogEvent::HiddenPassage | LogEvent::LevelIntro(_)
+                | LogEvent::LevelExited | LogEvent::CoreDeleted => LogKind::LevelEvent,
+            LogEvent::PlayerDeleted | LogEvent::PressSpace => LogKind::System,
+        }
+    }
+
+    // Everything taking part, the one doing it first
+    pub fn entities(&self) -> Vec<EntityType> {
+        match *self {
+            LogEvent::Attack(a, b, _) | LogEvent::Hit(a, b, _) | LogEvent::Kill(a, b) => vec![a, b],
+            LogEvent::CardActivated(t, _, _) | LogEvent::ModifierActivated(t, _)
+                | LogEvent::CardDiscarded(t, _) | LogEvent::ModifierStripped(t, _) => vec![t],
+            LogEvent::FragmentAbsorbed(_) | LogEvent::HiddenPassage | LogEvent::PlayerDeleted => {
+                vec![EntityType::Player]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub depth: i32,
+    // The turn being played when it happened. Turns keep counting up across levels.
+    pub turn: u32,
+    pub event: LogEvent,
+    // Where the entities involved were at the time
+    pub positions: Vec<Position>,
+    // Whether the player was involved or could see it happen
+    pub seen: bool,
+}
+
+impl LogEntry {
+    pub fn kind(&self) -> LogKind {
+        self.event.kind()
+    }
+
+    pub fn text(&self) -> String {
+        self.event.text()
+    }
+}
+
+// Everything logged over a whole run, carried from level to level and kept once the run is over
+pub struct MessageLog {
+    entries: Vec<LogEntry>,
 }
 
 impl MessageLog {
     fn new() -> MessageLog {
         MessageLog {
-            messages: Vec::new(),
+            entries: Vec::new(),
         }
     }
 }
 
 pub struct Level {
     level: i32,
+    final_level: i32,
+    turn: u32,
     last_id: Entity,
-    map: [[Square; MAP_WIDTH]; MAP_HEIGHT],
+    map: Grid,
     positions: BTreeMap<Entity, Position>,
 
     types: BTreeMap<Entity, EntityType>,
-    goals: BTreeMap<Entity, Goal>,
+    minds: BTreeMap<Entity, Mind>,
     decks: BTreeMap<Entity, Vec<CardState>>,
     modifiers: BTreeMap<Entity, Vec<Modification>>,
+    // Indices of the enemy cards the player has seen go off
+    scanned: BTreeMap<Entity, BTreeSet<usize>>,
 
     collected: BTreeMap<Card, i32>,
     log: MessageLog,
-    rng: StdRng,
+    death: Option<Death>,
+    rng: GameRng,
 }
 
-// TODO: impl
 pub struct LevelTransition {
     next_level: i32,
+    final_level: i32,
+    turn: u32,
     deck: Vec<Card>,
     collected: BTreeMap<Card, i32>,
     mutations: [Card; 3],
-    rng: StdRng,
+    log: MessageLog,
+    rng: GameRng,
+}
+
+#[derive(Clone, Debug)]
+pub struct Death {
+    pub cause: String,
+    pub killer: EntityType,
+    pub level: i32,
+    pub turns: u32,
+    pub seed: u32,
+}
+
+// The choices offered between levels. Add indexes into the offered mutations, Absorb into the
+// collected cards (in order) and Upgrade and Remove index into the deck.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Mutation {
+    Add(usize),
+    Absorb(usize),
+    Upgrade(usize),
+    Remove(usize),
+    Skip,
+}
+
+impl LevelTransition {
+    pub fn next_level(&self) -> i32 {
+        self.next_level
+    }
+
+    pub fn deck(&self) -> &[Card] {
+        &self.deck
+    }
+
+    pub fn offers(&self) -> &[Card] {
+        &self.mutations
+    }
+
+    pub fn collected(&self) -> &BTreeMap<Card, i32> {
+        &self.collected
+    }
+
+    pub fn log_entries(&self) -> &[LogEntry] {
+        &self.log.entries
+    }
+
+    pub fn can_mutate(&self, mutation: Mutation) -> bool {
+        match mutation {
+            Mutation::Add(index) => index < self.mutations.len(),
+            Mutation::Absorb(index) => index < self.collected.len(),
+            Mutation::Upgrade(index) => self.deck.get(index).and_then(|c| c.upgraded()).is_some(),
+            Mutation::Remove(index) => index < self.deck.len() && self.deck.len() > 1,
+            Mutation::Skip => true,
+        }
+    }
+
+    fn mutate(&mut self, mutation: Mutation) {
+        match mutation {
+            Mutation::Add(index) => {
+                self.deck.push(self.mutations[index]);
+            }
+            Mutation::Absorb(index) => {
+                let card = *self.collected.keys().nth(index).unwrap();
+                self.deck.push(card);
+                let remaining = {
+                    let count = self.collected.get_mut(&card).unwrap();
+                    *count -= 1;
+                    *count
+                };
+                if remaining <= 0 {
+                    self.collected.remove(&card);
+                }
+            }
+            Mutation::Upgrade(index) => {
+                if let Some(card) = self.deck[index].upgraded() {
+                    self.deck[index] = card;
+                }
+            }
+            Mutation::Remove(index) => {
+                self.deck.remove(index);
+            }
+            Mutation::Skip => {}
+        }
+    }
 }
 
 pub enum Game {
     Level(Level),
     Transition(LevelTransition),
-    Victory,
+    GameOver(Death, MessageLog),
+    Victory(MessageLog),
 }
 
+// The Option<Card> is a code fragment left lying around by something that was killed
 #[derive(Eq, PartialEq)]
 pub enum Glyph {
     Unknown,
-    Remembered(Tile),
-    Visible(Tile, Option<EntityType>),
+    Remembered(Tile, Option<Card>),
+    Visible(Tile, Option<EntityType>, Option<Card>),
 }
 
 impl Glyph {
     pub fn ch(&self) -> char {
         match self {
             &Glyph::Unknown => ' ',
-            &Glyph::Remembered(t) | &Glyph::Visible(t, None) => t.render(),
-            &Glyph::Visible(_, Some(e)) => e.render(),
+            &Glyph::Visible(_, Some(e), _) => e.render(),
+            &Glyph::Remembered(_, Some(_)) | &Glyph::Visible(_, None, Some(_)) => '*',
+            &Glyph::Remembered(t, None) | &Glyph::Visible(t, None, None) => t.render(),
         }
     }
 
     pub fn is_visible(&self) -> bool {
         match self {
-            &Glyph::Unknown | &Glyph::Remembered(_) => false,
-            &Glyph::Visible(_, _) => true,
+            &Glyph::Unknown | &Glyph::Remembered(_, _) => false,
+            &Glyph::Visible(_, _, _) => true,
         }
     }
 }
 
 impl Game {
     pub fn new(seed: u32) -> Game {
-        Game::Level(Level::new(seed))
+        Game::with_length(seed, DEFAULT_CAMPAIGN_LENGTH)
+    }
+
+    /// Starts a campaign that's won by destroying the core at the end of the given number of levels.
+    pub fn with_length(seed: u32, levels: i32) -> Game {
+        Game::Level(Level::new(seed, levels.max(1)))
     }
 
-    pub fn update(&mut self) {
+    pub fn level(&self) -> Option<&Level> {
+        match *self {
+            Game::Level(ref level) => Some(level),
+            _ => None,
+        }
+    }
+
+    pub fn level_mut(&mut self) -> Option<&mut Level> {
+        match *self {
+            Game::Level(ref mut level) => Some(level),
+            _ => None,
+        }
+    }
+
+    /// Everything logged so far this run, across all the levels played, including once it's over.
+    pub fn log_entries(&self) -> &[LogEntry] {
+        match *self {
+            Game::Level(ref level) => level.log_entries(),
+            Game::Transition(ref trans) => trans.log_entries(),
+            Game::GameOver(_, ref log) | Game::Victory(ref log) => &log.entries,
+        }
+    }
+
+    pub fn update(&mut self) -> bool {
         let update = match *self {
-            Game::Level(ref level) => {
-                if level.is_complete() {
-                    // TODO: change this back eventually
-                    Some(if level.level == 0 {
-                        Game::Victory
+            Game::Level(ref mut level) => {
+                if let Some(ref death) = level.death {
+                    let log = mem::replace(&mut level.log, MessageLog::new());
+                    Some(Game::GameOver(death.clone(), log))
+                } else if level.is_complete() {
+                    Some(if level.is_final() {
+                        Game::Victory(mem::replace(&mut level.log, MessageLog::new()))
                     } else {
                         let deck = level.decks.get(&PLAYER).iter().flat_map(|v| v.iter())
                             .map(|cs| cs.card).collect();
+                        let mutations = gen::generate_mutations(
+                            &mut level.rng, &level.collected, level.level + 1,
+                        );
                         Game::Transition(LevelTransition {
                             next_level: level.level + 1,
+                            final_level: level.final_level,
+                            turn: level.turn,
                             deck: deck,
                             collected: level.collected.clone(),
-                            // TODO: real card choices
-                            mutations: [Card::Attack(1), Card::Attack(2), Card::Attack(3)],
+                            mutations,
+                            log: mem::replace(&mut level.log, MessageLog::new()),
                             rng: level.rng.clone(),
                         })
                     })
@@ -239,19 +686,44 @@ impl Game {
                     None
                 }
             }
-            Game::Transition(ref trans) => {
-                // TODO: actual give the player new cards
-                Some(Game::Level(Level::next(trans.next_level, trans.deck.clone(), trans.rng.clone())))
-            }
-            Game::Victory => None,
+            // Waiting on the player to pick a mutation
+            Game::Transition(_) => None,
+            Game::GameOver(_, _) | Game::Victory(_) => None,
         };
         if let Some(update) = update {
             *self = update;
+            true
+        } else {
+            false
         }
     }
+
+    /// Applies the chosen mutation to the player's deck and moves on to the next level.
+    pub fn mutate(&mut self, mutation: Mutation) -> bool {
+        let next = match *self {
+            Game::Transition(ref mut trans) => {
+                if !trans.can_mutate(mutation) {
+                    return false;
+                }
+                trans.mutate(mutation);
+                Level::next(
+                    trans.next_level,
+                    trans.final_level,
+                    trans.turn,
+                    trans.deck.clone(),
+                    trans.collected.clone(),
+                    mem::replace(&mut trans.log, MessageLog::new()),
+                    trans.rng.clone(),
+                )
+            }
+            _ => { return false; }
+        };
+        *self = Game::Level(next);
+        true
+    }
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum Direction {
     Up,
     Down,
@@ -260,7 +732,7 @@ pub enum Direction {
 }
 
 impl Position {
-    fn step(self, dir: Direction) -> Position {
+    pub fn step(self, dir: Direction) -> Position {
         let Position { x, y } = self;
         match dir {
             Direction::Up => Position { x, y: y - 1 },
@@ -274,7 +746,7 @@ impl Position {
 // TODO: remove rest and toggle? They're not terribly useful and toggle in particular is fiddly to
 // use... may need to wait to see if additional content makes them suddenly useful to have (or make
 // sure not to add content that makes not having them annoying?)
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum Action {
     Move(Direction),
     Rest,
@@ -288,6 +760,41 @@ pub struct CardView {
     pub status: KnownCardStatus,
 }
 
+#[derive(Debug)]
+pub struct ModifierView {
+    pub modifier: Modifier,
+    pub source: EntityType,
+    pub card: Option<Card>,
+}
+
+#[derive(Debug)]
+pub struct EnemyView {
+    pub typ: EntityType,
+    pub cards: Vec<EnemyCardView>,
+    pub modifiers: Vec<ModifierView>,
+}
+
+// The card is only known once the player has scanned it by seeing it go off
+#[derive(Debug)]
+pub struct EnemyCardView {
+    pub card: Option<Card>,
+    pub in_hand: bool,
+}
+
+// How the player is holding up. Damage that gets through discards a card from the hand or strips a
+// modifier per point, and a point more than `health` deletes them.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct PlayerStatus {
+    pub hand: usize,
+    pub discarded: usize,
+    // Cards out of the hand because they're in play as modifiers
+    pub played: usize,
+    pub deck: usize,
+    pub modifiers: usize,
+    pub health: i32,
+    pub on_exit: bool,
+}
+
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum KnownCardStatus {
     Active,
@@ -326,110 +833,164 @@ impl Level {
         let sq = self.get_sq(pos);
         match sq.visibility {
             Visibility::Unknown => Glyph::Unknown,
-            Visibility::Remembered => Glyph::Remembered(sq.tile),
-            Visibility::Visible => Glyph::Visible(sq.tile, sq.entity.and_then(|e| self.types.get(&e)).cloned()),
+            Visibility::Remembered => Glyph::Remembered(sq.tile.apparent(), sq.fragment),
+            Visibility::Visible => Glyph::Visible(
+                sq.tile.apparent(), sq.entity.and_then(|e| self.types.get(&e)).cloned(), sq.fragment,
+            ),
         }
     }
 
-    pub fn message_log(&self) -> String {
-        self.log.messages.join("\n")
+    pub fn width(&self) -> i32 {
+        self.map.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.map.height
+    }
+
+    pub fn player_position(&self) -> Option<Position> {
+        self.positions.get(&PLAYER).cloned()
+    }
+
+    pub fn turn(&self) -> u32 {
+        self.turn
+    }
+
+    pub fn depth(&self) -> i32 {
+        self.level
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.level >= self.final_level
+    }
+
+    pub fn collected(&self) -> &BTreeMap<Card, i32> {
+        &self.collected
+    }
+
+    pub fn log_entries(&self) -> &[LogEntry] {
+        &self.log.entries
     }
 
     pub fn step(&mut self, action: Action) -> bool {
-        if self.is_complete() {
+        if self.is_complete() || self.is_dead() {
             return false;
         }
+        // Counted up front so the player's action is logged on the same turn as the responses
+        self.turn += 1;
         let success = self.do_action(PLAYER, action);
-        if success {
+        if !success {
+            self.turn -= 1;
+        } else {
             self.update_visibility(true);
             let entities: Vec<_> = self.types.keys().cloned().collect();
             for e in entities {
+                self.tick_modifiers(e);
                 self.take_turn(e);
             }
             self.update_visibility(false);
-            if self.is_complete() {
-                self.log.messages.push(String::from("Exiting level!"));
-                self.log.messages.push(String::from("Press [Space] to continue..."));
+            if self.is_dead() {
+                self.log(LogEvent::PlayerDeleted, &[]);
+                self.log(LogEvent::PressSpace, &[]);
+            } else if self.is_complete() {
+                if self.is_final() {
+                    self.log(LogEvent::CoreDeleted, &[]);
+                } else {
+                    self.log(LogEvent::LevelExited, &[PLAYER]);
+                }
+                self.log(LogEvent::PressSpace, &[]);
             }
         }
         success
     }
 
-    // TODO: set goals / have enemy memory
-    // TODO: make hunters smart enough to exploit strike/dodge
     fn take_turn(&mut self, entity: Entity) {
-        if entity == PLAYER {
-            return;
+        ai::take_turn(self, entity);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        if self.is_final() {
+            // The final level has no exit, just a core to delete
+            return self.types.contains_key(&PLAYER)
+                && self.types.values().all(|&t| t != EntityType::Core);
         }
-        let t = self.type_of(entity);
-        if t == EntityType::UnknownThing { return; }
-        let pos = match self.positions.get(&entity) {
-            Some(&pos) => pos,
-            None => { return; }
-        };
+        self.positions.get(&PLAYER).map(|&pos| self.get_sq(pos).tile == Tile::Exit).unwrap_or(false)
+    }
 
-        let target_pos;
-        {
-            let player_pos = match self.positions.get(&PLAYER) {
-                Some(&pos) => pos,
-                None => { return; }
-            };
+    pub fn is_dead(&self) -> bool {
+        self.death.is_some()
+    }
 
-            let vis = self.get_sq(pos).visibility == Visibility::Visible;
-            if vis {
-                target_pos = player_pos;
-                self.goals.insert(entity, Goal::Move(player_pos));
-            } else if let Some(&goal) = self.goals.get(&entity) {
-                match goal {
-                    Goal::Move(pos) => { target_pos = pos; }
-                    _ => { return; }
-                }
-            } else {
-                return;
+    pub fn player_modifiers(&self) -> Vec<ModifierView> {
+        self.modifier_views(PLAYER)
+    }
+
+    pub fn player_status(&self) -> PlayerStatus {
+        let deck = self.decks.get(&PLAYER).map(|d| &d[..]).unwrap_or(&[]);
+        let (mut hand, mut discarded, mut played) = (0, 0, 0);
+        for cs in deck {
+            match cs.status {
+                CardStatus::Active | CardStatus::Inactive => hand += 1,
+                CardStatus::Discarded => discarded += 1,
+                CardStatus::PlayedOn(_) => played += 1,
             }
         }
-
-        let (hdir, mut hweight) = if pos.x > target_pos.x {
-            (Direction::Left, pos.x - target_pos.x)
-        } else if pos.x < target_pos.x {
-            (Direction::Right, target_pos.x - pos.x)
-        } else {
-            (Direction::Left, 0)
-        };
-        let hpos = pos.step(hdir);
-        if hweight != 0 && hpos != target_pos && !self.is_open(hpos) {
-            hweight = 0;
+        PlayerStatus {
+            hand,
+            discarded,
+            played,
+            deck: deck.len(),
+            modifiers: self.modifiers.get(&PLAYER).map(|m| m.len()).unwrap_or(0),
+            health: self.health(PLAYER),
+            on_exit: self.positions.get(&PLAYER).map(|&pos| self.get_sq(pos).tile == Tile::Exit)
+                .unwrap_or(false),
         }
+    }
 
-        let (vdir, mut vweight) = if pos.y > target_pos.y {
-            (Direction::Up, pos.y - target_pos.y)
-        } else if pos.y < target_pos.y {
-            (Direction::Down, target_pos.y - pos.y)
-        } else {
-            (Direction::Up, 0)
-        };
-        let vpos = pos.step(vdir);
-        if vweight != 0 && vpos != target_pos && !self.is_open(vpos) {
-            vweight = 0;
-        }
+    // Cards in hand plus modifiers, which is how much damage it takes to get down to nothing
+    fn health(&self, entity: Entity) -> i32 {
+        let hand = self.decks.get(&entity)
+            .map(|d| d.iter().filter(|cs| cs.status.in_hand()).count())
+            .unwrap_or(0);
+        let mods = self.modifiers.get(&entity).map(|m| m.len()).unwrap_or(0);
+        (hand + mods) as i32
+    }
 
-        let action = if hweight + vweight > 0 {
-            if self.rng.gen_range(0, hweight + vweight) >= hweight {
-                Action::Move(vdir)
-            } else {
-                Action::Move(hdir)
-            }
-        } else {
-            Action::Rest
+    /// What the player can tell about the enemy standing at the given position, if they can see
+    /// one there.
+    pub fn enemy_view(&self, pos: Position) -> Option<EnemyView> {
+        let sq = self.get_sq(pos);
+        let entity = match sq.entity {
+            Some(e) if e != PLAYER && sq.visibility == Visibility::Visible => e,
+            _ => { return None; }
         };
-        self.do_action(entity, action);
+        let scanned = self.scanned.get(&entity);
+        Some(EnemyView {
+            typ: self.type_of(entity),
+            cards: self.decks.get(&entity).iter().flat_map(|v| v.iter()).enumerate()
+                .map(|(i, cs)| EnemyCardView {
+                    card: if scanned.map(|s| s.contains(&i)).unwrap_or(false) {
+                        Some(cs.card)
+                    } else {
+                        None
+                    },
+                    in_hand: cs.status.in_hand(),
+                }).collect(),
+            modifiers: self.modifier_views(entity),
+        })
     }
 
-    pub fn is_complete(&self) -> bool {
-        self.positions.get(&PLAYER).map(|&pos| self.get_sq(pos).tile == Tile::Exit).unwrap_or(false)
+    fn modifier_views(&self, entity: Entity) -> Vec<ModifierView> {
+        self.modifiers.get(&entity).iter().flat_map(|v| v.iter())
+            .map(|m| ModifierView {
+                modifier: m.modifier,
+                source: self.type_of(m.source),
+                card: self.decks.get(&m.source)
+                    .and_then(|d| d.get(m.source_index))
+                    .map(|cs| cs.card),
+            }).collect()
     }
 
-    // TODO: be able to return modifications
     pub fn player_deck(&self) -> Vec<CardView> {
         self.decks.get(&PLAYER).iter().flat_map(|v| v.iter())
             .map(|cs| CardView {
@@ -453,39 +1014,43 @@ impl Level {
             }).collect()
     }
 
-    fn new(seed: u32) -> Level {
-        let mut s = [0; 32];
-        s[0] = seed as u8;
-        s[1] = (seed>>8) as u8;
-        s[2] = (seed>>16) as u8;
-        s[3] = (seed>>24) as u8;
+    fn new(seed: u32, levels: i32) -> Level {
         // TODO
         // let deck = vec![Card::Attack(1), Card::Block];
         let deck = vec![
             Card::Attack(1), Card::Kill(1), Card::Strike, Card::Push,
             Card::Dodge, Card::Defend(2), Card::Block,
         ];
-        Level::next(0, deck, StdRng::from_seed(s))
+        Level::next(0, levels - 1, 0, deck, BTreeMap::new(), MessageLog::new(), GameRng::new(seed))
     }
 
-    fn next(next_level: i32, player_deck: Vec<Card>, rng: StdRng) -> Level {
+    fn next(
+        next_level: i32,
+        final_level: i32,
+        turn: u32,
+        player_deck: Vec<Card>,
+        collected: BTreeMap<Card, i32>,
+        log: MessageLog,
+        rng: GameRng,
+    ) -> Level {
         let mut level = Level {
             level: next_level,
+            final_level,
+            turn,
             last_id: PLAYER,
-            map: [[Square {
-                tile: Tile::Wall,
-                entity: None,
-                visibility: Visibility::Unknown,
-            }; MAP_WIDTH]; MAP_HEIGHT],
+            // The generator decides how big the level is
+            map: Grid::new(0, 0),
             positions: BTreeMap::new(),
 
             types: BTreeMap::new(),
-            goals: BTreeMap::new(),
+            minds: BTreeMap::new(),
             decks: BTreeMap::new(),
             modifiers: BTreeMap::new(),
+            scanned: BTreeMap::new(),
 
-            collected: BTreeMap::new(),
-            log: MessageLog::new(),
+            collected,
+            log,
+            death: None,
             rng,
         };
         level.types.insert(PLAYER, EntityType::Player);
@@ -499,13 +1064,14 @@ impl Level {
 
     fn get_sq(&self, pos: Position) -> Square {
         self.map
-            .get(pos.y as usize)
-            .and_then(|r| r.get(pos.x as usize))
+            .get(pos)
             .cloned()
             .unwrap_or(Square {
                 tile: Tile::Wall,
                 entity: None,
+                fragment: None,
                 visibility: Visibility::Unknown,
+                lit: false,
             })
     }
 
@@ -517,6 +1083,7 @@ impl Level {
             Some(&pos) => pos,
             None => { return false; }
         };
+        let mut revealed = None;
         let event = match action {
             Action::Move(dir) => {
                 let new_pos = pos.step(dir);
@@ -525,6 +1092,10 @@ impl Level {
                     Event::Attack { target, damage: 1, direction: Some(dir) }
                 } else if self.is_open(new_pos) {
                     Event::Move { destination: new_pos, direction: Some(dir) }
+                } else if dst.tile == Tile::Secret && entity == PLAYER {
+                    // Feeling around for the hidden door takes the player's turn
+                    revealed = Some(new_pos);
+                    Event::Wait
                 } else {
                    return false;
                 }
@@ -537,8 +1108,16 @@ impl Level {
                 _ => { return false; }
             },
         };
-        if entity == PLAYER && self.log.messages.last().map(|s| &**s) != Some("---") {
-            self.log.messages.push(format!("---"));
+        if let Some(pos) = revealed {
+            self.set_tile(pos, Tile::Door);
+            self.log(LogEvent::HiddenPassage, &[entity]);
+        }
+        match event {
+            Event::Disable(_) | Event::Enable(_) => {}
+            // The turn is lost outright, without giving cards a chance to act on it
+            _ => if self.consume_slow(entity) {
+                return true;
+            },
         }
         self.process(entity, event);
         true
@@ -550,18 +1129,22 @@ impl Level {
 
     // TODO: be more careful about overwriting existing entities?
     fn move_entity(&mut self, entity: Entity, pos: Position) -> bool {
-        if !self.is_open(pos) {
+        if !self.map.contains(pos) || !self.is_open(pos) {
             return false;
         }
         self.remove_entity(entity);
-        self.map[pos.y as usize][pos.x as usize].entity = Some(entity);
+        if let Some(sq) = self.map.get_mut(pos) {
+            sq.entity = Some(entity);
+        }
         self.positions.insert(entity, pos);
         true
     }
 
     fn remove_entity(&mut self, entity: Entity) -> Option<Position> {
         if let Some(&old_pos) = self.positions.get(&entity) {
-            self.map[old_pos.y as usize][old_pos.x as usize].entity = None;
+            if let Some(sq) = self.map.get_mut(old_pos) {
+                sq.entity = None;
+            }
             Some(old_pos)
         } else {
             None
@@ -570,20 +1153,21 @@ impl Level {
 
     fn destroy_entity(&mut self, entity: Entity) {
         self.remove_entity(entity);
+        self.positions.remove(&entity);
         self.types.remove(&entity);
-        self.goals.remove(&entity);
-        // TODO: handle removing modifiers
+        self.minds.remove(&entity);
         self.decks.remove(&entity);
-        self.modifiers.remove(&entity);
+        self.scanned.remove(&entity);
+        for m in self.modifiers.remove(&entity).unwrap_or_default() {
+            self.release_card(entity, m);
+        }
     }
 
     fn update_visibility(&mut self, clear: bool) {
         if clear {
-            for y in 0..MAP_HEIGHT {
-                for x in 0..MAP_WIDTH {
-                    if self.map[y][x].visibility == Visibility::Visible {
-                        self.map[y][x].visibility = Visibility::Remembered;
-                    }
+            for sq in self.map.squares.iter_mut() {
+                if sq.visibility == Visibility::Visible {
+                    sq.visibility = Visibility::Remembered;
                 }
             }
         }
@@ -591,44 +1175,49 @@ impl Level {
             None => { return; }
             Some(&pos) => pos,
         };
-        for &(pdir, sdir) in &[
-            (Direction::Up, Direction::Left),
-            (Direction::Up, Direction::Right),
-            (Direction::Down, Direction::Left),
-            (Direction::Down, Direction::Right),
-            (Direction::Left, Direction::Up),
-            (Direction::Left, Direction::Down),
-            (Direction::Right, Direction::Up),
-            (Direction::Right, Direction::Down),
-        ] {
-            let mut visited = HashSet::new();
-            let mut pending = Vec::new();
-            pending.push(ppos);
-            while let Some(pos) = pending.pop() {
-                self.mark_visible(pos);
-                let sq = self.get_sq(pos);
-                if sq.tile == Tile::Wall || sq.tile == Tile::Door && sq.entity.is_none() {
-                    continue;
-                }
-                let fwd = pos.step(pdir);
-                if visited.insert(fwd) {
-                    pending.push(fwd);
-                }
-                let diag = fwd.step(sdir);
-                if visited.insert(diag) {
-                    pending.push(diag);
-                }
-            }
+        let mut seen = Vec::new();
+        self.field_of_view(ppos, EntityType::Player.sight_radius(), |pos| seen.push(pos));
+        for pos in seen {
+            self.mark_visible(pos);
         }
     }
 
+    // Doors only let anything be seen through them while something is holding them open
+    fn blocks_sight(&self, pos: Position) -> bool {
+        let sq = self.get_sq(pos);
+        sq.tile.is_solid() || sq.tile == Tile::Door && sq.entity.is_none()
+    }
+
+    fn field_of_view<F: FnMut(Position)>(&self, origin: Position, radius: i32, mut seen: F) {
+        fov::field_of_view(origin, radius, |pos| self.blocks_sight(pos), |pos| {
+            let (dx, dy) = (pos.x - origin.x, pos.y - origin.y);
+            let close = dx * dx + dy * dy <= DARK_SIGHT_RADIUS * DARK_SIGHT_RADIUS + DARK_SIGHT_RADIUS;
+            if close || self.get_sq(pos).lit {
+                seen(pos);
+            }
+        });
+    }
+
+    fn can_see(&self, entity: Entity, target: Position) -> bool {
+        let pos = match self.positions.get(&entity) {
+            Some(&pos) => pos,
+            None => { return false; }
+        };
+        let mut found = false;
+        self.field_of_view(pos, self.type_of(entity).sight_radius(), |p| if p == target {
+            found = true;
+        });
+        found
+    }
+
     fn mark_visible(&mut self, pos: Position) {
-        self.map[pos.y as usize][pos.x as usize].visibility = Visibility::Visible;
+        if let Some(sq) = self.map.get_mut(pos) {
+            sq.visibility = Visibility::Visible;
+        }
     }
 
-    // TODO: level type selection
     fn generate(&mut self) {
-        gen::generate_map(self, gen::LevelType::Test);
+        gen::generate_level(self);
     }
 
     fn make_entity(&mut self, typ: EntityType) -> Entity {
@@ -647,7 +1236,9 @@ impl Level {
     }
 
     fn set_tile(&mut self, pos: Position, tile: Tile) {
-        self.map[pos.y as usize][pos.x as usize].tile = tile;
+        if let Some(sq) = self.map.get_mut(pos) {
+            sq.tile = tile;
+        }
     }
 
     fn type_of(&self, entity: Entity) -> EntityType {
@@ -655,12 +1246,11 @@ impl Level {
     }
 
     fn try_trigger(&mut self, entity: Entity, index: usize, event: &mut Event) -> bool {
-        // TODO: figure out how to apply modifiers
         let c = self.decks.get(&entity)
             .and_then(|d| d.get(index))
             .cloned().unwrap();
         if c.status != CardStatus::Active { return false; }
-        let triggered = match self.activate_card(entity, c.card, event) {
+        let triggered = match self.activate_card(entity, index, c.card, event) {
             CardOutcome::Continue => false,
             CardOutcome::Done => true,
             CardOutcome::Discard => {
@@ -681,21 +1271,32 @@ impl Level {
                 true
             }
         };
+        if triggered && entity != PLAYER && self.is_visible(entity) {
+            self.scanned.entry(entity).or_default().insert(index);
+        }
         triggered
     }
 
-    fn activate_card(&mut self, entity: Entity, card: Card, event: &mut Event) -> CardOutcome {
-        // TODO: clean up logging crap
+    fn is_visible(&self, entity: Entity) -> bool {
+        match self.positions.get(&entity) {
+            Some(&pos) => self.get_sq(pos).visibility == Visibility::Visible,
+            None => false,
+        }
+    }
+
+    fn activate_card(
+        &mut self, entity: Entity, index: usize, card: Card, event: &mut Event,
+    ) -> CardOutcome {
         let t = self.type_of(entity);
         match (card, event) {
             (Card::Attack(atk), Event::Attack { damage, .. }) => {
-                self.log.messages.push(format!("({:?}'s {:?} card activated)", t, card));
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
                 *damage += atk;
                 CardOutcome::Done
             }
             (Card::Defend(def), Event::Defend { damage, .. }) =>  {
                 if *damage >= def {
-                    self.log.messages.push(format!("({:?}'s {:?} card activated and was discarded)", t, card));
+                    self.log(LogEvent::CardActivated(t, card, true), &[entity]);
                     *damage -= def;
                     CardOutcome::Discard
                 } else {
@@ -703,13 +1304,8 @@ impl Level {
                 }
             }
             (Card::Kill(atk), &mut Event::Attack { target, damage, direction }) => {
-                let mod_count = self.modifiers.get_mut(&target).map(|m| m.len()).unwrap_or(0);
-                let hand_size = self.decks.get_mut(&target).map(|d| {
-                    d.iter_mut().filter(|c| c.status.in_hand()).count()
-                }).unwrap_or(0);
-                let health = (hand_size + mod_count) as i32;
-                if damage + atk > health {
-                    self.log.messages.push(format!("({:?}'s {:?} card activated)", t, card));
+                if damage + atk > self.health(target) {
+                    self.log(LogEvent::CardActivated(t, card, false), &[entity]);
                     self.execute(target, Event::Defend {
                         source: entity,
                         damage: damage + atk,
@@ -723,7 +1319,7 @@ impl Level {
             (Card::Strike, &mut Event::Move { destination, direction: Some(dir) }) => {
                 let target_pos = destination.step(dir);
                 if let Some(target) = self.get_sq(target_pos).entity {
-                    self.log.messages.push(format!("({:?}'s {:?} card activated)", t, card));
+                    self.log(LogEvent::CardActivated(t, card, false), &[entity]);
                     self.execute(entity, Event::Move {
                         destination,
                         direction: Some(dir),
@@ -744,13 +1340,13 @@ impl Level {
                     None => { return CardOutcome::Continue; }
                 } .step(dir);
                 let dst = self.get_sq(new_pos);
-                if dst.tile == Tile::Wall {
+                if dst.tile.is_solid() {
                     return CardOutcome::Continue;
                 }
                 // TODO: Really allow dodging into other entities? It's hilarious and all, but a
                 // little unhelpful for the thing doing the dodging (but dodging is still really
                 // good, so maybe that's a reasonable tradeoff)
-                self.log.messages.push(format!("({:?}'s {:?} card activated)", t, card));
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
                 if let Some(target) = dst.entity {
                     self.execute(entity, Event::Attack {
                         target,
@@ -767,7 +1363,7 @@ impl Level {
                 }
             }
             (Card::Block, Event::Defend { .. }) =>  {
-                self.log.messages.push(format!("({:?}'s {:?} card activated and was discarded)", t, card));
+                self.log(LogEvent::CardActivated(t, card, true), &[entity]);
                 CardOutcome::DiscardAndCancel
             }
             (Card::Push, &mut Event::Attack { target, direction: Some(dir), .. }) => {
@@ -777,10 +1373,10 @@ impl Level {
                 };
                 let new_pos = target_pos.step(dir);
                 let dst = self.get_sq(new_pos);
-                if dst.tile == Tile::Wall {
+                if dst.tile.is_solid() {
                     return CardOutcome::Continue;
                 }
-                self.log.messages.push(format!("({:?}'s {:?} card activated)", t, card));
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
                 if let Some(secondary_target) = dst.entity {
                     self.execute(target, Event::Attack {
                         target: secondary_target,
@@ -799,12 +1395,142 @@ impl Level {
                 }
                 CardOutcome::Done
             }
+            (Card::Shield, Event::Recover) => {
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
+                self.play_card(entity, index, entity, Modifier::Shield);
+                CardOutcome::Cancel
+            }
+            (Card::Slow(turns), &mut Event::Attack { target, .. }) => {
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
+                self.play_card(entity, index, target, Modifier::Slow(turns));
+                CardOutcome::Done
+            }
+            (Card::Virus(turns), &mut Event::Attack { target, .. }) => {
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
+                self.play_card(entity, index, target, Modifier::Virus(turns));
+                CardOutcome::Done
+            }
+            (Card::Overclock, Event::Wait) => {
+                self.log(LogEvent::CardActivated(t, card, false), &[entity]);
+                self.play_card(entity, index, entity, Modifier::Overclock);
+                CardOutcome::Done
+            }
             _ => CardOutcome::Continue,
         }
     }
 
+    fn play_card(&mut self, entity: Entity, index: usize, target: Entity, modifier: Modifier) {
+        if !self.types.contains_key(&target) {
+            return;
+        }
+        self.set_card_status(entity, index, CardStatus::PlayedOn(target));
+        self.modifiers.entry(target).or_default().push(Modification {
+            source: entity,
+            source_index: index,
+            modifier,
+        });
+    }
+
+    // Puts the card that a modification came from back into its owner's discard pile (assuming it
+    // hasn't already been recovered)
+    fn release_card(&mut self, bearer: Entity, m: Modification) {
+        if self.get_card_status(m.source, m.source_index) == Some(CardStatus::PlayedOn(bearer)) {
+            self.set_card_status(m.source, m.source_index, CardStatus::Discarded);
+        }
+    }
+
+    fn strip_modifier(&mut self, entity: Entity, index: usize) {
+        let m = match self.modifiers.get_mut(&entity) {
+            Some(mods) if index < mods.len() => mods.remove(index),
+            _ => { return; }
+        };
+        self.release_card(entity, m);
+    }
+
+    fn find_modifier<F: Fn(Modifier) -> bool>(&self, entity: Entity, f: F) -> Option<usize> {
+        self.modifiers.get(&entity).and_then(|mods| mods.iter().position(|m| f(m.modifier)))
+    }
+
+    fn apply_modifiers(&mut self, entity: Entity, event: &mut Event) {
+        let t = self.type_of(entity);
+        match *event {
+            Event::Defend { .. } => {
+                if let Some(i) = self.find_modifier(entity, |m| m == Modifier::Shield) {
+                    self.log(LogEvent::ModifierActivated(t, Modifier::Shield), &[entity]);
+                    self.strip_modifier(entity, i);
+                    *event = Event::None;
+                }
+            }
+            Event::Attack { ref mut damage, .. } => {
+                if let Some(i) = self.find_modifier(entity, |m| m == Modifier::Overclock) {
+                    self.log(LogEvent::ModifierActivated(t, Modifier::Overclock), &[entity]);
+                    self.strip_modifier(entity, i);
+                    *damage += 2;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn consume_slow(&mut self, entity: Entity) -> bool {
+        let slow = self.find_modifier(entity, |m| matches!(m, Modifier::Slow(_)));
+        let i = match slow {
+            Some(i) => i,
+            None => { return false; }
+        };
+        let t = self.type_of(entity);
+        let m = self.modifiers[&entity][i].modifier;
+        self.log(LogEvent::ModifierActivated(t, m), &[entity]);
+        let expired = match self.modifiers.get_mut(&entity).map(|mods| &mut mods[i].modifier) {
+            Some(&mut Modifier::Slow(ref mut turns)) => {
+                *turns -= 1;
+                *turns <= 0
+            }
+            _ => false,
+        };
+        if expired {
+            self.strip_modifier(entity, i);
+        }
+        true
+    }
+
+    fn tick_modifiers(&mut self, entity: Entity) {
+        let viruses: Vec<Modification> = self.modifiers.get(&entity).iter()
+            .flat_map(|mods| mods.iter())
+            .filter(|m| matches!(m.modifier, Modifier::Virus(_)))
+            .cloned()
+            .collect();
+        for virus in viruses {
+            // Damage from an earlier virus can strip modifiers and shift the rest around, so find
+            // this one again (if it's still there) rather than trusting its old index
+            let key = (virus.source, virus.source_index);
+            let found = self.modifiers.get(&entity)
+                .and_then(|mods| mods.iter().position(|m| (m.source, m.source_index) == key));
+            let i = match found {
+                Some(i) => i,
+                None => continue,
+            };
+            let m = self.modifiers[&entity][i];
+            let turns = match m.modifier {
+                Modifier::Virus(turns) => turns,
+                _ => continue,
+            };
+            let t = self.type_of(entity);
+            self.log(LogEvent::ModifierActivated(t, m.modifier), &[entity]);
+            if turns <= 1 {
+                self.strip_modifier(entity, i);
+            } else {
+                self.modifiers.get_mut(&entity).unwrap()[i].modifier = Modifier::Virus(turns - 1);
+            }
+            self.execute(entity, Event::Defend { source: m.source, damage: 1, direction: None });
+            if !self.types.contains_key(&entity) {
+                return;
+            }
+        }
+    }
+
     fn process(&mut self, entity: Entity, mut event: Event) -> Event {
-        // TODO: include modifiers
+        self.apply_modifiers(entity, &mut event);
         // TODO: optimize shuffling to already exclude non-active cards
         let shuffled = if let Some(d) = self.decks.get(&entity) {
             let mut v: Vec<usize> = (0..d.len()).collect();
@@ -822,13 +1548,13 @@ impl Level {
         event
     }
 
-    // TODO: (more) player action messages
     fn execute(&mut self, entity: Entity, event: Event) {
-        // TODO: message only for visible attacks?
         let et = self.type_of(entity);
         match event {
             Event::Move { destination, .. } => {
-                self.move_entity(entity, destination);
+                if self.move_entity(entity, destination) && entity == PLAYER {
+                    self.absorb_fragment(destination);
+                }
             }
             Event::Recover => {
                 self.recover(entity);
@@ -842,22 +1568,78 @@ impl Level {
             }
             Event::Attack { target, damage, direction } => {
                 let tt = self.type_of(target);
-                self.log.messages.push(format!("{:?} attacks the {:?} for {} damage!", et, tt, damage));
+                self.log(LogEvent::Attack(et, tt, damage), &[entity, target]);
                 self.process(target, Event::Defend { source: entity, damage, direction });
             }
             Event::Defend { source, damage, .. } => {
                 let st = self.type_of(source);
-                self.log.messages.push(format!("{:?} hits the {:?} for {} damage!", st, et, damage));
+                self.log(LogEvent::Hit(st, et, damage), &[source, entity]);
                 let fatal = self.take_damage(entity, damage);
                 if fatal {
+                    if entity == PLAYER {
+                        self.death = Some(Death {
+                            cause: format!("Hit for {} damage by {}", damage, st.a()),
+                            killer: st,
+                            level: self.level,
+                            turns: self.turn,
+                            seed: self.rng.seed(),
+                        });
+                    }
+                    let pos = self.positions.get(&entity).cloned();
+                    let dropped = self.drop_fragment(entity);
+                    self.log(LogEvent::Kill(st, et), &[source, entity]);
                     self.destroy_entity(entity);
-                    self.log.messages.push(format!("{:?} kills the {:?}!", st, et));
+                    if let (Some(pos), Some(card)) = (pos, dropped) {
+                        if self.get_sq(pos).fragment.is_none() {
+                            self.set_fragment(pos, Some(card));
+                        }
+                    }
                 }
             }
             Event::None => {}
         }
     }
 
+    // Picks a random card out of a dying entity's deck to leave behind
+    fn drop_fragment(&mut self, entity: Entity) -> Option<Card> {
+        if entity == PLAYER {
+            return None;
+        }
+        let deck_size = self.decks.get(&entity).map(|d| d.len()).unwrap_or(0);
+        if deck_size == 0 {
+            return None;
+        }
+        let index = self.rng.gen_range(0, deck_size);
+        self.decks.get(&entity).map(|d| d[index].card)
+    }
+
+    fn absorb_fragment(&mut self, pos: Position) {
+        if let Some(card) = self.get_sq(pos).fragment {
+            self.set_fragment(pos, None);
+            *self.collected.entry(card).or_insert(0) += 1;
+            self.log(LogEvent::FragmentAbsorbed(card), &[PLAYER]);
+        }
+    }
+
+    fn log(&mut self, event: LogEvent, involved: &[Entity]) {
+        let positions: Vec<_> = involved.iter().filter_map(|e| self.positions.get(e).cloned()).collect();
+        let seen = involved.is_empty() || involved.contains(&PLAYER)
+            || positions.iter().any(|&pos| self.get_sq(pos).visibility == Visibility::Visible);
+        self.log.entries.push(LogEntry {
+            depth: self.level,
+            turn: self.turn,
+            event,
+            positions,
+            seen,
+        });
+    }
+
+    fn set_fragment(&mut self, pos: Position, fragment: Option<Card>) {
+        if let Some(sq) = self.map.get_mut(pos) {
+            sq.fragment = fragment;
+        }
+    }
+
     fn set_card_status(&mut self, entity: Entity, index: usize, status: CardStatus) {
         self.decks.get_mut(&entity).and_then(|d| d.get_mut(index)).map(|cs| {
             cs.status = status;
@@ -870,46 +1652,113 @@ impl Level {
 
     fn take_damage(&mut self, entity: Entity, damage: i32) -> bool {
         let t = self.type_of(entity);
-        let (tmp1, tmp2) = (&mut Vec::new(), &mut Vec::new());
-        let deck = self.decks.get_mut(&entity).unwrap_or(tmp1);
-        let mods = self.modifiers.get_mut(&entity).unwrap_or(tmp2);
-        let mut hand: Vec<_> = deck.iter_mut().filter(|c| c.status.in_hand()).collect();
-        for _ in 0..damage {
-            let hand_size = hand.len();
-            let mod_count = mods.len();
-            let option_count = hand_size + mod_count;
-            if option_count == 0 {
-                return true;
-            }
-            let selection = self.rng.gen_range(0, option_count);
-            if selection < hand_size {
-                {
-                    let c = &mut hand[selection];
-                    self.log.messages.push(format!("({:?}'s {:?} card was discarded by damage)", t, c.card));
-                    c.status = CardStatus::Discarded;
+        let mut stripped = Vec::new();
+        let mut lost = Vec::new();
+        let mut fatal = false;
+        {
+            let (tmp1, tmp2) = (&mut Vec::new(), &mut Vec::new());
+            let deck = self.decks.get_mut(&entity).unwrap_or(tmp1);
+            let mods = self.modifiers.get_mut(&entity).unwrap_or(tmp2);
+            let mut hand: Vec<_> = deck.iter_mut().filter(|c| c.status.in_hand()).collect();
+            for _ in 0..damage {
+                let hand_size = hand.len();
+                let mod_count = mods.len();
+                let option_count = hand_size + mod_count;
+                if option_count == 0 {
+                    fatal = true;
+                    break;
+                }
+                let selection = self.rng.gen_range(0, option_count);
+                if selection < hand_size {
+                    {
+                        let c = &mut hand[selection];
+                        lost.push(LogEvent::CardDiscarded(t, c.card));
+                        c.status = CardStatus::Discarded;
+                    }
+                    hand.remove(selection);
+                } else {
+                    let m = mods.remove(selection - hand_size);
+                    lost.push(LogEvent::ModifierStripped(t, m.modifier));
+                    stripped.push(m);
                 }
-                hand.remove(selection);
-            } else {
-                mods.remove(option_count - hand_size);
             }
         }
-        false
+        for event in lost {
+            self.log(event, &[entity]);
+        }
+        for m in stripped {
+            self.release_card(entity, m);
+        }
+        fatal
     }
 
     fn recover(&mut self, entity: Entity) {
         // let mut card = None;
+        let mut played_on = None;
         if let Some(deck) = self.decks.get_mut(&entity) {
-            let mut discard: Vec<_> = deck.iter_mut().filter(|c| !c.status.in_hand()).collect();
-            if let Some(sel) = self.rng.choose_mut(&mut discard) {
-                // TODO: handle removing modifiers
-                sel.status = CardStatus::Active;
+            let mut discard: Vec<_> = deck.iter_mut().enumerate()
+                .filter(|(_, c)| !c.status.in_hand())
+                .collect();
+            if let Some(&mut (index, ref mut sel)) = self.rng.choose_mut(&mut discard) {
+                if let CardStatus::PlayedOn(target) = sel.status {
+                    played_on = Some((index, target));
+                } else {
+                    sel.status = CardStatus::Active;
+                }
                 // card = Some(sel.card);
             }
         }
+        // Recovering a card that was played pulls its modifier back off of whatever it was on,
+        // releasing the card like any other stripped modifier before it goes back in hand
+        if let Some((index, target)) = played_on {
+            let found = self.modifiers.get(&target).and_then(|mods| {
+                mods.iter().position(|m| m.source == entity && m.source_index == index)
+            });
+            if let Some(i) = found {
+                let t = self.type_of(target);
+                let m = self.modifiers[&target][i].modifier;
+                self.log(LogEvent::ModifierStripped(t, m), &[target]);
+                self.strip_modifier(target, i);
+            }
+            self.set_card_status(entity, index, CardStatus::Active);
+        }
         // TODO: message?
         // if let Some(card) = card {
         //     let t = self.type_of(entity);
-        //     self.log.messages.push(format!("({:?} draws its {:?} card)", t, card));
+        //     self.log(LogEvent::CardDrawn(t, card), &[entity]);
         // }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_overclock(slowed: bool) -> Level {
+        let mut level = Level::new(0, DEFAULT_CAMPAIGN_LENGTH);
+        let overclock = CardState { card: Card::Overclock, status: CardStatus::Active };
+        level.decks.insert(PLAYER, vec![overclock]);
+        level.modifiers.remove(&PLAYER);
+        if slowed {
+            let slow = Modification {
+                source: PLAYER,
+                source_index: 0,
+                modifier: Modifier::Slow(2),
+            };
+            level.modifiers.insert(PLAYER, vec![slow]);
+        }
+        level
+    }
+
+    #[test]
+    fn slowed_turn_skips_cards() {
+        let mut level = with_overclock(false);
+        assert!(level.do_action(PLAYER, Action::Wait));
+        assert!(level.find_modifier(PLAYER, |m| m == Modifier::Overclock).is_some());
+
+        let mut level = with_overclock(true);
+        assert!(level.do_action(PLAYER, Action::Wait));
+        assert!(level.find_modifier(PLAYER, |m| m == Modifier::Overclock).is_none());
+        assert!(level.find_modifier(PLAYER, |m| m == Modifier::Slow(1)).is_some());
+    }
+}
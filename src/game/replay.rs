@@ -0,0 +1,212 @@
+use std::io::{self, Read, Write};
+
+use super::*;
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum Input {
+    Action(Action),
+    Update,
+    Mutate(Mutation),
+}
+
+// A run is fully determined by its seed and the inputs fed into it, so that's all a replay needs
+// (plus a hash of the final state to check that nothing diverged along the way).
+#[derive(Clone)]
+pub struct Replay {
+    pub seed: u32,
+    pub levels: i32,
+    pub inputs: Vec<Input>,
+    pub hash: u64,
+}
+
+impl Replay {
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Re-runs every recorded input from the start, returning None if any of them fails to apply.
+    pub fn play(&self) -> Option<Recorder> {
+        let mut recorder = Recorder::with_length(self.seed, self.levels);
+        for &input in &self.inputs {
+            if !recorder.apply(input) {
+                return None;
+            }
+        }
+        Some(recorder)
+    }
+
+    pub fn verify(&self) -> bool {
+        self.play().map(|r| state_hash(r.game()) == self.hash).unwrap_or(false)
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(save::write_replay(self).as_bytes())
+    }
+
+    pub fn load<R: Read>(r: &mut R) -> io::Result<Replay> {
+        let mut s = String::new();
+        r.read_to_string(&mut s)?;
+        save::read_replay(&s)
+    }
+}
+
+// FNV-1a over the save representation, which covers everything that affects future play
+pub fn state_hash(game: &Game) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in save::write_game(game).bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Wraps a Game, keeping track of every input that changed its state.
+pub struct Recorder {
+    seed: u32,
+    levels: i32,
+    game: Game,
+    inputs: Vec<Input>,
+}
+
+impl Recorder {
+    pub fn new(seed: u32) -> Recorder {
+        Recorder::with_length(seed, DEFAULT_CAMPAIGN_LENGTH)
+    }
+
+    pub fn with_length(seed: u32, levels: i32) -> Recorder {
+        Recorder {
+            seed,
+            levels,
+            game: Game::with_length(seed, levels),
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    // How many levels the campaign being played runs to
+    pub fn levels(&self) -> i32 {
+        self.levels
+    }
+
+    pub fn step(&mut self, action: Action) -> bool {
+        let success = match self.game {
+            Game::Level(ref mut level) => level.step(action),
+            _ => false,
+        };
+        if success {
+            self.inputs.push(Input::Action(action));
+        }
+        success
+    }
+
+    pub fn update(&mut self) -> bool {
+        let updated = self.game.update();
+        if updated {
+            self.inputs.push(Input::Update);
+        }
+        updated
+    }
+
+    pub fn mutate(&mut self, mutation: Mutation) -> bool {
+        let mutated = self.game.mutate(mutation);
+        if mutated {
+            self.inputs.push(Input::Mutate(mutation));
+        }
+        mutated
+    }
+
+    pub fn apply(&mut self, input: Input) -> bool {
+        match input {
+            Input::Action(action) => self.step(action),
+            Input::Update => self.update(),
+            Input::Mutate(mutation) => self.mutate(mutation),
+        }
+    }
+
+    pub fn replay(&self) -> Replay {
+        Replay {
+            seed: self.seed,
+            levels: self.levels,
+            inputs: self.inputs.clone(),
+            hash: state_hash(&self.game),
+        }
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(save::write_recording(&self.game, &self.replay()).as_bytes())
+    }
+
+    pub fn load<R: Read>(r: &mut R) -> io::Result<Recorder> {
+        let mut s = String::new();
+        r.read_to_string(&mut s)?;
+        let (game, replay) = save::read_recording(&s)?;
+        Ok(Recorder {
+            seed: replay.seed,
+            levels: replay.levels,
+            game,
+            inputs: replay.inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    // Plays a few hundred inputs of a short campaign, picking whatever's appropriate for the state
+    // the game is in
+    fn record(seed: u32) -> Recorder {
+        let mut recorder = Recorder::with_length(seed, 2);
+        let mut rng = GameRng::new(seed);
+        while recorder.replay().len() < 300 {
+            let input = match *recorder.game() {
+                Game::Level(_) => Input::Action(match rng.next_u32() % 7 {
+                    0 => Action::Move(Direction::Up),
+                    1 => Action::Move(Direction::Down),
+                    2 => Action::Move(Direction::Left),
+                    3 => Action::Move(Direction::Right),
+                    4 => Action::Rest,
+                    5 => Action::Wait,
+                    _ => Action::Toggle(rng.next_u32() as usize % 8),
+                }),
+                Game::Transition(_) => Input::Mutate(Mutation::Skip),
+                Game::GameOver(_, _) | Game::Victory(_) => break,
+            };
+            recorder.apply(input);
+            recorder.update();
+        }
+        recorder
+    }
+
+    #[test]
+    fn saved_replay_verifies() {
+        // Some runs get deleted early, but between them there should be plenty of inputs
+        let mut total = 0;
+        for seed in 0..5 {
+            let replay = record(seed).replay();
+            total += replay.len();
+            let mut saved = Vec::new();
+            replay.save(&mut saved).unwrap();
+            let loaded = Replay::load(&mut &saved[..]).unwrap();
+            assert_eq!(loaded.len(), replay.len());
+            assert!(loaded.inputs == replay.inputs);
+            assert!(loaded.verify());
+        }
+        assert!(total >= 300);
+    }
+
+    #[test]
+    fn tampered_hash_fails() {
+        let mut replay = record(1).replay();
+        replay.hash ^= 1;
+        assert!(!replay.verify());
+    }
+}
@@ -0,0 +1,86 @@
+use rand::{Error, RngCore, SeedableRng, StdRng};
+
+// StdRng doesn't expose its internal state, so instead of saving that directly we keep track of
+// the seed and how many 32-bit words have been pulled out of the generator. The underlying block
+// generator hands out words strictly in order (a u64 is just two consecutive words), so reseeding
+// and discarding the same number of words lands on exactly the same state.
+#[derive(Clone)]
+pub struct GameRng {
+    seed: u32,
+    words: u64,
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u32) -> GameRng {
+        let mut s = [0; 32];
+        s[0] = seed as u8;
+        s[1] = (seed>>8) as u8;
+        s[2] = (seed>>16) as u8;
+        s[3] = (seed>>24) as u8;
+        GameRng {
+            seed,
+            words: 0,
+            rng: StdRng::from_seed(s),
+        }
+    }
+
+    pub fn restore(seed: u32, words: u64) -> GameRng {
+        let mut rng = GameRng::new(seed);
+        for _ in 0..words {
+            rng.next_u32();
+        }
+        rng
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn words(&self) -> u64 {
+        self.words
+    }
+}
+
+// Byte counts are rounded up to words by hand since div_ceil needs a much newer compiler than the
+// rest of the crate
+#[allow(clippy::manual_div_ceil)]
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.words += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.words += 2;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.words += (dest.len() as u64 + 3) / 4;
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.words += (dest.len() as u64 + 3) / 4;
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_matches_mixed_draws() {
+        let mut rng = GameRng::new(7);
+        rng.next_u32();
+        rng.next_u64();
+        rng.fill_bytes(&mut [0; 7]);
+        assert_eq!(rng.words(), 5);
+        let mut restored = GameRng::restore(rng.seed(), rng.words());
+        for _ in 0..16 {
+            assert_eq!(rng.next_u32(), restored.next_u32());
+        }
+    }
+}
@@ -0,0 +1,985 @@
+use std::fmt::Display;
+use std::io;
+use std::str::{FromStr, SplitWhitespace};
+
+use super::*;
+use super::replay::{Input, Replay};
+
+// The save format is a whitespace separated list of tokens, with newlines thrown in to keep it
+// vaguely readable. Bump SAVE_VERSION whenever the layout changes. Replays share the version since
+// their final state hashes are computed over the save format.
+const SAVE_MAGIC: &str = "cyberphage-save";
+const REPLAY_MAGIC: &str = "cyberphage-replay";
+const SAVE_VERSION: u32 = 1;
+
+fn invalid<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad save file: {}", msg)))
+}
+
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn token<T: Display>(&mut self, t: T) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push(' ');
+        }
+        self.out.push_str(&t.to_string());
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+    }
+
+    fn string(&mut self, s: &str) {
+        let mut escaped = String::from("'");
+        for ch in s.chars() {
+            match ch {
+                '\\' => escaped.push_str("\\\\"),
+                ' ' => escaped.push_str("\\s"),
+                '\n' => escaped.push_str("\\n"),
+                '\t' => escaped.push_str("\\t"),
+                ch => escaped.push(ch),
+            }
+        }
+        self.token(escaped);
+    }
+
+    fn position(&mut self, pos: Position) {
+        self.token(pos.x);
+        self.token(pos.y);
+    }
+
+    fn entity(&mut self, entity: Entity) {
+        self.token(entity.0);
+    }
+
+    fn entity_type(&mut self, t: EntityType) {
+        self.token(match t {
+            EntityType::UnknownThing => "unknown",
+            EntityType::Player => "player",
+            EntityType::Defender => "defender",
+            EntityType::Hunter => "hunter",
+            EntityType::Reaper => "reaper",
+            EntityType::Core => "core",
+        });
+    }
+
+    fn card(&mut self, card: Card) {
+        match card {
+            Card::Attack(n) => { self.token("attack"); self.token(n); }
+            Card::Defend(n) => { self.token("defend"); self.token(n); }
+            Card::Kill(n) => { self.token("kill"); self.token(n); }
+            Card::Strike => self.token("strike"),
+            Card::Dodge => self.token("dodge"),
+            Card::Block => self.token("block"),
+            Card::Push => self.token("push"),
+            Card::Shield => self.token("shield"),
+            Card::Slow(n) => { self.token("slow"); self.token(n); }
+            Card::Virus(n) => { self.token("virus"); self.token(n); }
+            Card::Overclock => self.token("overclock"),
+        }
+    }
+
+    fn card_status(&mut self, status: CardStatus) {
+        match status {
+            CardStatus::Active => self.token("active"),
+            CardStatus::Inactive => self.token("inactive"),
+            CardStatus::Discarded => self.token("discarded"),
+            CardStatus::PlayedOn(e) => { self.token("played"); self.entity(e); }
+        }
+    }
+
+    fn modifier(&mut self, modifier: Modifier) {
+        match modifier {
+            Modifier::Shield => self.token("shield"),
+            Modifier::Slow(n) => { self.token("slow"); self.token(n); }
+            Modifier::Virus(n) => { self.token("virus"); self.token(n); }
+            Modifier::Overclock => self.token("overclock"),
+        }
+    }
+
+    fn goal(&mut self, goal: Goal) {
+        match goal {
+            Goal::Move(pos) => { self.token("move"); self.position(pos); }
+            Goal::Attack(e) => { self.token("attack"); self.entity(e); }
+            Goal::Wait => self.token("wait"),
+            Goal::Rest => self.token("rest"),
+        }
+    }
+
+    fn mind(&mut self, mind: &Mind) {
+        self.token(match mind.state {
+            AiState::Idle => "idle",
+            AiState::Patrol => "patrol",
+            AiState::Investigate => "investigate",
+            AiState::Hunt => "hunt",
+            AiState::Flee => "flee",
+        });
+        self.goal(mind.goal);
+        match mind.memory {
+            Some(memory) => {
+                self.token("remembers");
+                self.position(memory.pos);
+                self.token(memory.age);
+            }
+            None => self.token("forgot"),
+        }
+    }
+
+    fn death(&mut self, death: &Death) {
+        self.entity_type(death.killer);
+        self.token(death.level);
+        self.token(death.turns);
+        self.token(death.seed);
+        self.string(&death.cause);
+    }
+
+    fn log_event(&mut self, event: &LogEvent) {
+        match *event {
+            LogEvent::Attack(a, b, damage) => {
+                self.token("attack");
+                self.entity_type(a);
+                self.entity_type(b);
+                self.token(damage);
+            }
+            LogEvent::Hit(a, b, damage) => {
+                self.token("hit");
+                self.entity_type(a);
+                self.entity_type(b);
+                self.token(damage);
+            }
+            LogEvent::CardActivated(t, card, discarded) => {
+                self.token(if discarded { "spent" } else { "activated" });
+                self.entity_type(t);
+                self.card(card);
+            }
+            LogEvent::ModifierActivated(t, m) => { self.token("modified"); self.entity_type(t); self.modifier(m); }
+            LogEvent::CardDiscarded(t, card) => { self.token("discarded"); self.entity_type(t); self.card(card); }
+            LogEvent::ModifierStripped(t, m) => { self.token("stripped"); self.entity_type(t); self.modifier(m); }
+            LogEvent::Kill(a, b) => { self.token("kill"); self.entity_type(a); self.entity_type(b); }
+            LogEvent::FragmentAbsorbed(card) => { self.token("absorbed"); self.card(card); }
+            LogEvent::HiddenPassage => self.token("passage"),
+            LogEvent::LevelIntro(ref text) => { self.token("intro"); self.string(text); }
+            LogEvent::LevelExited => self.token("exited"),
+            LogEvent::CoreDeleted => self.token("core-deleted"),
+            LogEvent::PlayerDeleted => self.token("deleted"),
+            LogEvent::PressSpace => self.token("continue"),
+        }
+    }
+
+    fn log_entry(&mut self, entry: &LogEntry) {
+        self.token(entry.depth);
+        self.token(entry.turn);
+        self.token(if entry.seen { "seen" } else { "unseen" });
+        self.token(entry.positions.len());
+        for &pos in &entry.positions {
+            self.position(pos);
+        }
+        self.log_event(&entry.event);
+    }
+
+    fn log(&mut self, log: &MessageLog) {
+        self.token("log");
+        self.token(log.entries.len());
+        self.newline();
+        for entry in &log.entries {
+            self.log_entry(entry);
+            self.newline();
+        }
+    }
+
+    fn collected(&mut self, collected: &BTreeMap<Card, i32>) {
+        self.token("collected");
+        self.token(collected.len());
+        self.newline();
+        for (&card, &count) in collected {
+            self.card(card);
+            self.token(count);
+            self.newline();
+        }
+    }
+
+    fn rng(&mut self, rng: &GameRng) {
+        self.token("rng");
+        self.token(rng.seed());
+        self.token(rng.words());
+        self.newline();
+    }
+
+    fn level(&mut self, level: &Level) {
+        self.token("depth");
+        self.token(level.level);
+        self.token(level.final_level);
+        self.token("turn");
+        self.token(level.turn);
+        self.token("last_id");
+        self.entity(level.last_id);
+        self.newline();
+
+        self.token("map");
+        self.token(level.map.width);
+        self.token(level.map.height);
+        self.newline();
+        for row in level.map.rows() {
+            // Secret doors render as walls, so they need a character of their own here
+            self.token(row.iter().map(|sq| match sq.tile {
+                Tile::Secret => 's',
+                t => t.render(),
+            }).collect::<String>());
+            self.token(row.iter().map(|sq| match sq.visibility {
+                Visibility::Visible => 'v',
+                Visibility::Remembered => 'r',
+                Visibility::Unknown => 'u',
+            }).collect::<String>());
+            self.token(row.iter().map(|sq| if sq.lit { 'l' } else { 'd' }).collect::<String>());
+            self.newline();
+        }
+
+        let fragments: Vec<_> = level.map.rows().enumerate().flat_map(|(y, row)| {
+            row.iter().enumerate().filter_map(move |(x, sq)| {
+                sq.fragment.map(|card| (Position { x: x as i32, y: y as i32 }, card))
+            })
+        }).collect();
+        self.token("fragments");
+        self.token(fragments.len());
+        self.newline();
+        for (pos, card) in fragments {
+            self.position(pos);
+            self.card(card);
+            self.newline();
+        }
+
+        self.token("positions");
+        self.token(level.positions.len());
+        self.newline();
+        for (&e, &pos) in &level.positions {
+            self.entity(e);
+            self.position(pos);
+            self.newline();
+        }
+
+        self.token("types");
+        self.token(level.types.len());
+        self.newline();
+        for (&e, &t) in &level.types {
+            self.entity(e);
+            self.entity_type(t);
+            self.newline();
+        }
+
+        self.token("minds");
+        self.token(level.minds.len());
+        self.newline();
+        for (&e, mind) in &level.minds {
+            self.entity(e);
+            self.mind(mind);
+            self.newline();
+        }
+
+        self.token("decks");
+        self.token(level.decks.len());
+        self.newline();
+        for (&e, deck) in &level.decks {
+            self.entity(e);
+            self.token(deck.len());
+            for cs in deck {
+                self.card(cs.card);
+                self.card_status(cs.status);
+            }
+            self.newline();
+        }
+
+        self.token("modifiers");
+        self.token(level.modifiers.len());
+        self.newline();
+        for (&e, mods) in &level.modifiers {
+            self.entity(e);
+            self.token(mods.len());
+            for m in mods {
+                self.entity(m.source);
+                self.token(m.source_index);
+                self.modifier(m.modifier);
+            }
+            self.newline();
+        }
+
+        self.token("scanned");
+        self.token(level.scanned.len());
+        self.newline();
+        for (&e, indices) in &level.scanned {
+            self.entity(e);
+            self.token(indices.len());
+            for &i in indices {
+                self.token(i);
+            }
+            self.newline();
+        }
+
+        self.collected(&level.collected);
+
+        self.log(&level.log);
+
+        self.token("death");
+        match level.death {
+            Some(ref death) => { self.token("some"); self.death(death); }
+            None => self.token("none"),
+        }
+        self.newline();
+
+        self.rng(&level.rng);
+    }
+
+    fn transition(&mut self, trans: &LevelTransition) {
+        self.token("next_level");
+        self.token(trans.next_level);
+        self.token(trans.final_level);
+        self.token("turn");
+        self.token(trans.turn);
+        self.newline();
+
+        self.token("deck");
+        self.token(trans.deck.len());
+        for &card in &trans.deck {
+            self.card(card);
+        }
+        self.newline();
+
+        self.collected(&trans.collected);
+
+        self.token("mutations");
+        self.token(trans.mutations.len());
+        for &card in &trans.mutations {
+            self.card(card);
+        }
+        self.newline();
+
+        self.log(&trans.log);
+
+        self.rng(&trans.rng);
+    }
+
+    fn header(&mut self, magic: &str) {
+        self.token(magic);
+        self.token(SAVE_VERSION);
+        self.newline();
+    }
+
+    fn input(&mut self, input: Input) {
+        match input {
+            Input::Action(Action::Move(Direction::Up)) => self.token("up"),
+            Input::Action(Action::Move(Direction::Down)) => self.token("down"),
+            Input::Action(Action::Move(Direction::Left)) => self.token("left"),
+            Input::Action(Action::Move(Direction::Right)) => self.token("right"),
+            Input::Action(Action::Rest) => self.token("rest"),
+            Input::Action(Action::Wait) => self.token("wait"),
+            Input::Action(Action::Toggle(index)) => { self.token("toggle"); self.token(index); }
+            Input::Update => self.token("update"),
+            Input::Mutate(Mutation::Add(index)) => { self.token("add"); self.token(index); }
+            Input::Mutate(Mutation::Absorb(index)) => { self.token("absorb"); self.token(index); }
+            Input::Mutate(Mutation::Upgrade(index)) => { self.token("upgrade"); self.token(index); }
+            Input::Mutate(Mutation::Remove(index)) => { self.token("remove"); self.token(index); }
+            Input::Mutate(Mutation::Skip) => self.token("skip"),
+        }
+    }
+
+    fn replay(&mut self, replay: &Replay) {
+        self.token("seed");
+        self.token(replay.seed);
+        self.token("levels");
+        self.token(replay.levels);
+        self.newline();
+
+        self.token("inputs");
+        self.token(replay.inputs.len());
+        self.newline();
+        for chunk in replay.inputs.chunks(16) {
+            for &input in chunk {
+                self.input(input);
+            }
+            self.newline();
+        }
+
+        self.token("hash");
+        self.token(format!("{:016x}", replay.hash));
+        self.newline();
+    }
+
+    fn game(&mut self, game: &Game) {
+        match *game {
+            Game::Level(ref level) => {
+                self.token("level");
+                self.newline();
+                self.level(level);
+            }
+            Game::Transition(ref trans) => {
+                self.token("transition");
+                self.newline();
+                self.transition(trans);
+            }
+            Game::GameOver(ref death, ref log) => {
+                self.token("game_over");
+                self.death(death);
+                self.newline();
+                self.log(log);
+            }
+            Game::Victory(ref log) => {
+                self.token("victory");
+                self.newline();
+                self.log(log);
+            }
+        }
+    }
+}
+
+pub fn write_game(game: &Game) -> String {
+    let mut w = Writer { out: String::new() };
+    w.header(SAVE_MAGIC);
+    w.game(game);
+    w.token("end");
+    w.newline();
+    w.out
+}
+
+pub fn write_recording(game: &Game, replay: &Replay) -> String {
+    let mut w = Writer { out: String::new() };
+    w.header(SAVE_MAGIC);
+    w.game(game);
+    w.replay(replay);
+    w.token("end");
+    w.newline();
+    w.out
+}
+
+pub fn write_replay(replay: &Replay) -> String {
+    let mut w = Writer { out: String::new() };
+    w.header(REPLAY_MAGIC);
+    w.replay(replay);
+    w.token("end");
+    w.newline();
+    w.out
+}
+
+struct Reader<'a> {
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn token(&mut self) -> io::Result<&'a str> {
+        match self.tokens.next() {
+            Some(t) => Ok(t),
+            None => invalid("unexpected end of file"),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> io::Result<()> {
+        let t = self.token()?;
+        if t != expected {
+            return invalid(&format!("expected {:?}, found {:?}", expected, t));
+        }
+        Ok(())
+    }
+
+    fn parse<T: FromStr>(&mut self) -> io::Result<T> {
+        let t = self.token()?;
+        match t.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => invalid(&format!("unexpected token {:?}", t)),
+        }
+    }
+
+    fn count(&mut self, section: &str) -> io::Result<usize> {
+        self.expect(section)?;
+        self.parse()
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let t = self.token()?;
+        if !t.starts_with('\'') {
+            return invalid("expected string");
+        }
+        let mut s = String::new();
+        let mut chars = t[1..].chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                s.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('\\') => s.push('\\'),
+                Some('s') => s.push(' '),
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                _ => { return invalid("bad string escape"); }
+            }
+        }
+        Ok(s)
+    }
+
+    fn position(&mut self) -> io::Result<Position> {
+        let x = self.parse()?;
+        let y = self.parse()?;
+        Ok(Position { x, y })
+    }
+
+    fn entity(&mut self) -> io::Result<Entity> {
+        Ok(Entity(self.parse()?))
+    }
+
+    fn entity_type(&mut self) -> io::Result<EntityType> {
+        Ok(match self.token()? {
+            "unknown" => EntityType::UnknownThing,
+            "player" => EntityType::Player,
+            "defender" => EntityType::Defender,
+            "hunter" => EntityType::Hunter,
+            "reaper" => EntityType::Reaper,
+            "core" => EntityType::Core,
+            t => { return invalid(&format!("unknown entity type {:?}", t)); }
+        })
+    }
+
+    fn card(&mut self) -> io::Result<Card> {
+        Ok(match self.token()? {
+            "attack" => Card::Attack(self.parse()?),
+            "defend" => Card::Defend(self.parse()?),
+            "kill" => Card::Kill(self.parse()?),
+            "strike" => Card::Strike,
+            "dodge" => Card::Dodge,
+            "block" => Card::Block,
+            "push" => Card::Push,
+            "shield" => Card::Shield,
+            "slow" => Card::Slow(self.parse()?),
+            "virus" => Card::Virus(self.parse()?),
+            "overclock" => Card::Overclock,
+            t => { return invalid(&format!("unknown card {:?}", t)); }
+        })
+    }
+
+    fn card_status(&mut self) -> io::Result<CardStatus> {
+        Ok(match self.token()? {
+            "active" => CardStatus::Active,
+            "inactive" => CardStatus::Inactive,
+            "discarded" => CardStatus::Discarded,
+            "played" => CardStatus::PlayedOn(self.entity()?),
+            t => { return invalid(&format!("unknown card status {:?}", t)); }
+        })
+    }
+
+    fn modifier(&mut self) -> io::Result<Modifier> {
+        Ok(match self.token()? {
+            "shield" => Modifier::Shield,
+            "slow" => Modifier::Slow(self.parse()?),
+            "virus" => Modifier::Virus(self.parse()?),
+            "overclock" => Modifier::Overclock,
+            t => { return invalid(&format!("unknown modifier {:?}", t)); }
+        })
+    }
+
+    fn goal(&mut self) -> io::Result<Goal> {
+        Ok(match self.token()? {
+            "move" => Goal::Move(self.position()?),
+            "attack" => Goal::Attack(self.entity()?),
+            "wait" => Goal::Wait,
+            "rest" => Goal::Rest,
+            t => { return invalid(&format!("unknown goal {:?}", t)); }
+        })
+    }
+
+    fn mind(&mut self) -> io::Result<Mind> {
+        let state = match self.token()? {
+            "idle" => AiState::Idle,
+            "patrol" => AiState::Patrol,
+            "investigate" => AiState::Investigate,
+            "hunt" => AiState::Hunt,
+            "flee" => AiState::Flee,
+            t => { return invalid(&format!("unknown AI state {:?}", t)); }
+        };
+        let goal = self.goal()?;
+        let memory = match self.token()? {
+            "remembers" => Some(Memory { pos: self.position()?, age: self.parse()? }),
+            "forgot" => None,
+            t => { return invalid(&format!("unknown memory {:?}", t)); }
+        };
+        Ok(Mind { state, goal, memory })
+    }
+
+    fn death(&mut self) -> io::Result<Death> {
+        let killer = self.entity_type()?;
+        let level = self.parse()?;
+        let turns = self.parse()?;
+        let seed = self.parse()?;
+        let cause = self.string()?;
+        Ok(Death { cause, killer, level, turns, seed })
+    }
+
+    fn log_event(&mut self) -> io::Result<LogEvent> {
+        Ok(match self.token()? {
+            "attack" => LogEvent::Attack(self.entity_type()?, self.entity_type()?, self.parse()?),
+            "hit" => LogEvent::Hit(self.entity_type()?, self.entity_type()?, self.parse()?),
+            "activated" => LogEvent::CardActivated(self.entity_type()?, self.card()?, false),
+            "spent" => LogEvent::CardActivated(self.entity_type()?, self.card()?, true),
+            "modified" => LogEvent::ModifierActivated(self.entity_type()?, self.modifier()?),
+            "discarded" => LogEvent::CardDiscarded(self.entity_type()?, self.card()?),
+            "stripped" => LogEvent::ModifierStripped(self.entity_type()?, self.modifier()?),
+            "kill" => LogEvent::Kill(self.entity_type()?, self.entity_type()?),
+            "absorbed" => LogEvent::FragmentAbsorbed(self.card()?),
+            "passage" => LogEvent::HiddenPassage,
+            "intro" => LogEvent::LevelIntro(self.string()?),
+            "exited" => LogEvent::LevelExited,
+            "core-deleted" => LogEvent::CoreDeleted,
+            "deleted" => LogEvent::PlayerDeleted,
+            "continue" => LogEvent::PressSpace,
+            t => { return invalid(&format!("unknown log event {:?}", t)); }
+        })
+    }
+
+    fn log_entry(&mut self) -> io::Result<LogEntry> {
+        let depth = self.parse()?;
+        let turn = self.parse()?;
+        let seen = match self.token()? {
+            "seen" => true,
+            "unseen" => false,
+            t => { return invalid(&format!("unknown log visibility {:?}", t)); }
+        };
+        let mut positions = Vec::new();
+        for _ in 0..self.parse::<usize>()? {
+            positions.push(self.position()?);
+        }
+        let event = self.log_event()?;
+        Ok(LogEntry { depth, turn, event, positions, seen })
+    }
+
+    fn log(&mut self) -> io::Result<MessageLog> {
+        let mut log = MessageLog::new();
+        for _ in 0..self.count("log")? {
+            log.entries.push(self.log_entry()?);
+        }
+        Ok(log)
+    }
+
+    fn collected(&mut self) -> io::Result<BTreeMap<Card, i32>> {
+        let mut collected = BTreeMap::new();
+        for _ in 0..self.count("collected")? {
+            let card = self.card()?;
+            collected.insert(card, self.parse()?);
+        }
+        Ok(collected)
+    }
+
+    fn rng(&mut self) -> io::Result<GameRng> {
+        self.expect("rng")?;
+        let seed = self.parse()?;
+        let words = self.parse()?;
+        Ok(GameRng::restore(seed, words))
+    }
+
+    fn level(&mut self) -> io::Result<Level> {
+        self.expect("depth")?;
+        let depth = self.parse()?;
+        let final_level = self.parse()?;
+        self.expect("turn")?;
+        let turn = self.parse()?;
+        self.expect("last_id")?;
+        let last_id = self.entity()?;
+
+        self.expect("map")?;
+        let width: usize = self.parse()?;
+        let height: usize = self.parse()?;
+        // Anything this big is a corrupt save rather than a real level
+        if width > 1000 || height > 1000 {
+            return invalid("map too large");
+        }
+        let mut map = Grid::new(width as i32, height as i32);
+        for row in map.squares.chunks_mut(width.max(1)) {
+            let tiles = self.token()?;
+            let vis = self.token()?;
+            let light = self.token()?;
+            if tiles.len() != width || vis.len() != width || light.len() != width {
+                return invalid("wrong map row length");
+            }
+            let squares = row.iter_mut().zip(tiles.chars()).zip(vis.chars()).zip(light.chars());
+            for (((sq, t), v), l) in squares {
+                sq.tile = match t {
+                    '.' => Tile::Floor,
+                    '#' => Tile::Wall,
+                    '+' => Tile::Door,
+                    '>' => Tile::Exit,
+                    's' => Tile::Secret,
+                    _ => { return invalid("unknown tile"); }
+                };
+                sq.visibility = match v {
+                    'v' => Visibility::Visible,
+                    'r' => Visibility::Remembered,
+                    'u' => Visibility::Unknown,
+                    _ => { return invalid("unknown visibility"); }
+                };
+                sq.lit = match l {
+                    'l' => true,
+                    'd' => false,
+                    _ => { return invalid("unknown lighting"); }
+                };
+            }
+        }
+
+        for _ in 0..self.count("fragments")? {
+            let pos = self.position()?;
+            let card = self.card()?;
+            match map.get_mut(pos) {
+                Some(sq) => { sq.fragment = Some(card); }
+                None => { return invalid("fragment out of bounds"); }
+            }
+        }
+
+        let mut positions = BTreeMap::new();
+        for _ in 0..self.count("positions")? {
+            let e = self.entity()?;
+            positions.insert(e, self.position()?);
+        }
+
+        let mut types = BTreeMap::new();
+        for _ in 0..self.count("types")? {
+            let e = self.entity()?;
+            types.insert(e, self.entity_type()?);
+        }
+
+        let mut minds = BTreeMap::new();
+        for _ in 0..self.count("minds")? {
+            let e = self.entity()?;
+            minds.insert(e, self.mind()?);
+        }
+
+        let mut decks = BTreeMap::new();
+        for _ in 0..self.count("decks")? {
+            let e = self.entity()?;
+            let mut deck = Vec::new();
+            for _ in 0..self.parse::<usize>()? {
+                let card = self.card()?;
+                let status = self.card_status()?;
+                deck.push(CardState { card, status });
+            }
+            decks.insert(e, deck);
+        }
+
+        let mut modifiers = BTreeMap::new();
+        for _ in 0..self.count("modifiers")? {
+            let e = self.entity()?;
+            let mut mods = Vec::new();
+            for _ in 0..self.parse::<usize>()? {
+                let source = self.entity()?;
+                let source_index = self.parse()?;
+                let modifier = self.modifier()?;
+                mods.push(Modification { source, source_index, modifier });
+            }
+            modifiers.insert(e, mods);
+        }
+
+        let mut scanned = BTreeMap::new();
+        for _ in 0..self.count("scanned")? {
+            let e = self.entity()?;
+            let mut indices = BTreeSet::new();
+            for _ in 0..self.parse::<usize>()? {
+                indices.insert(self.parse()?);
+            }
+            scanned.insert(e, indices);
+        }
+
+        let collected = self.collected()?;
+
+        let log = self.log()?;
+
+        self.expect("death")?;
+        let death = match self.token()? {
+            "some" => Some(self.death()?),
+            "none" => None,
+            t => { return invalid(&format!("unexpected token {:?}", t)); }
+        };
+
+        let rng = self.rng()?;
+
+        for (e, &pos) in &positions {
+            match map.get_mut(pos) {
+                Some(sq) => { sq.entity = Some(*e); }
+                None => { return invalid("entity out of bounds"); }
+            }
+        }
+
+        Ok(Level {
+            level: depth,
+            final_level,
+            turn,
+            last_id,
+            map,
+            positions,
+
+            types,
+            minds,
+            decks,
+            modifiers,
+            scanned,
+
+            collected,
+            log,
+            death,
+            rng,
+        })
+    }
+
+    fn transition(&mut self) -> io::Result<LevelTransition> {
+        self.expect("next_level")?;
+        let next_level = self.parse()?;
+        let final_level = self.parse()?;
+        self.expect("turn")?;
+        let turn = self.parse()?;
+
+        let mut deck = Vec::new();
+        for _ in 0..self.count("deck")? {
+            deck.push(self.card()?);
+        }
+
+        let collected = self.collected()?;
+
+        let mut mutations = [Card::Attack(1); 3];
+        if self.count("mutations")? != mutations.len() {
+            return invalid("wrong number of mutations");
+        }
+        for m in mutations.iter_mut() {
+            *m = self.card()?;
+        }
+
+        let log = self.log()?;
+
+        let rng = self.rng()?;
+
+        Ok(LevelTransition {
+            next_level,
+            final_level,
+            turn,
+            deck,
+            collected,
+            mutations,
+            log,
+            rng,
+        })
+    }
+
+    fn header(&mut self, magic: &str) -> io::Result<()> {
+        self.expect(magic)?;
+        let version: u32 = self.parse()?;
+        if version != SAVE_VERSION {
+            return invalid(&format!("unsupported version {}", version));
+        }
+        Ok(())
+    }
+
+    fn input(&mut self) -> io::Result<Input> {
+        Ok(match self.token()? {
+            "up" => Input::Action(Action::Move(Direction::Up)),
+            "down" => Input::Action(Action::Move(Direction::Down)),
+            "left" => Input::Action(Action::Move(Direction::Left)),
+            "right" => Input::Action(Action::Move(Direction::Right)),
+            "rest" => Input::Action(Action::Rest),
+            "wait" => Input::Action(Action::Wait),
+            "toggle" => Input::Action(Action::Toggle(self.parse()?)),
+            "update" => Input::Update,
+            "add" => Input::Mutate(Mutation::Add(self.parse()?)),
+            "absorb" => Input::Mutate(Mutation::Absorb(self.parse()?)),
+            "upgrade" => Input::Mutate(Mutation::Upgrade(self.parse()?)),
+            "remove" => Input::Mutate(Mutation::Remove(self.parse()?)),
+            "skip" => Input::Mutate(Mutation::Skip),
+            t => { return invalid(&format!("unknown input {:?}", t)); }
+        })
+    }
+
+    fn replay(&mut self) -> io::Result<Replay> {
+        self.expect("seed")?;
+        let seed = self.parse()?;
+        self.expect("levels")?;
+        let levels = self.parse()?;
+
+        let mut inputs = Vec::new();
+        for _ in 0..self.count("inputs")? {
+            inputs.push(self.input()?);
+        }
+
+        self.expect("hash")?;
+        let hash = match u64::from_str_radix(self.token()?, 16) {
+            Ok(hash) => hash,
+            Err(_) => { return invalid("bad hash"); }
+        };
+
+        Ok(Replay { seed, levels, inputs, hash })
+    }
+
+    fn game(&mut self) -> io::Result<Game> {
+        Ok(match self.token()? {
+            "level" => Game::Level(self.level()?),
+            "transition" => Game::Transition(self.transition()?),
+            "game_over" => {
+                let death = self.death()?;
+                Game::GameOver(death, self.log()?)
+            }
+            "victory" => Game::Victory(self.log()?),
+            t => { return invalid(&format!("unknown game state {:?}", t)); }
+        })
+    }
+}
+
+pub fn read_recording(s: &str) -> io::Result<(Game, Replay)> {
+    let mut r = Reader { tokens: s.split_whitespace() };
+    r.header(SAVE_MAGIC)?;
+    let game = r.game()?;
+    let replay = r.replay()?;
+    r.expect("end")?;
+    Ok((game, replay))
+}
+
+pub fn read_replay(s: &str) -> io::Result<Replay> {
+    let mut r = Reader { tokens: s.split_whitespace() };
+    r.header(REPLAY_MAGIC)?;
+    let replay = r.replay()?;
+    r.expect("end")?;
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::*;
+    use super::super::replay::state_hash;
+
+    fn random_action(rng: &mut GameRng) -> Action {
+        match rng.next_u32() % 6 {
+            0 => Action::Move(Direction::Up),
+            1 => Action::Move(Direction::Down),
+            2 => Action::Move(Direction::Left),
+            3 => Action::Move(Direction::Right),
+            4 => Action::Rest,
+            _ => Action::Wait,
+        }
+    }
+
+    #[test]
+    fn save_and_load_mid_level() {
+        for seed in 0..10 {
+            let mut game = Recorder::new(seed);
+            let mut actions = GameRng::new(seed);
+            for _ in 0..50 {
+                game.step(random_action(&mut actions));
+                game.update();
+            }
+            assert!(game.game().level().is_some());
+            let mut saved = Vec::new();
+            game.save(&mut saved).unwrap();
+            let mut loaded = Recorder::load(&mut &saved[..]).unwrap();
+            assert_eq!(state_hash(game.game()), state_hash(loaded.game()));
+            for _ in 0..50 {
+                let action = random_action(&mut actions);
+                assert_eq!(game.step(action), loaded.step(action));
+                assert_eq!(game.update(), loaded.update());
+                assert_eq!(state_hash(game.game()), state_hash(loaded.game()));
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use super::*;
+use super::ai::{dijkstra_map, distance, downhill};
+
+// Enemies look at what's in their own hands to work out how best to go after their target,
+// instead of just walking straight at it. Returning None falls back on doing exactly that.
+pub fn attack_action(level: &mut Level, entity: Entity, target: Entity) -> Option<Action> {
+    let pos = *level.positions.get(&entity)?;
+    let target_pos = *level.positions.get(&target)?;
+    let cards: Vec<Card> = level.decks.get(&entity).iter().flat_map(|d| d.iter())
+        .filter(|cs| cs.status == CardStatus::Active)
+        .map(|cs| cs.card)
+        .collect();
+    match level.type_of(entity) {
+        EntityType::Hunter if cards.contains(&Card::Strike) => {
+            // With a Dodge ready, getting hit up close isn't much of a worry
+            if adjacent(pos, target_pos) && cards.contains(&Card::Dodge) {
+                return None;
+            }
+            lunge(level, pos, target_pos)
+        }
+        EntityType::Defender if cards.contains(&Card::Push) => shove(level, pos, target_pos),
+        EntityType::Reaper => {
+            let kill = cards.iter().filter_map(|&c| match c {
+                Card::Kill(n) => Some(n),
+                _ => None,
+            }).max()?;
+            // Kill goes off when the hit would take out more than the target has left
+            if 1 + kill > level.health(target) {
+                None
+            } else {
+                hang_back(level, pos, target_pos)
+            }
+        }
+        _ => None,
+    }
+}
+
+// Strike turns a step toward something two squares away into a lunge, so line one of those up
+fn lunge(level: &mut Level, pos: Position, target_pos: Position) -> Option<Action> {
+    let open = |level: &Level, p: Position| p == pos || level.is_open(p);
+    for &dir in DIRECTIONS {
+        let mid = pos.step(dir);
+        if mid.step(dir) == target_pos && level.is_open(mid) {
+            return Some(Action::Move(dir));
+        }
+    }
+    let spots: Vec<_> = DIRECTIONS.iter().filter_map(|&dir| {
+        let mid = target_pos.step(dir);
+        let spot = mid.step(dir);
+        if open(level, mid) && open(level, spot) { Some((spot, 0)) } else { None }
+    }).collect();
+    if spots.is_empty() {
+        return None;
+    }
+    let map = dijkstra_map(level, &spots);
+    downhill(level, &map, pos, None).map(Action::Move)
+}
+
+// Push shoves the target back a square, so come at it from a side where that pins it against a
+// wall or knocks it into something else
+fn shove(level: &mut Level, pos: Position, target_pos: Position) -> Option<Action> {
+    let mut spots = Vec::new();
+    for &dir in DIRECTIONS {
+        let spot = target_pos.step(dir);
+        let behind = level.get_sq(target_pos.step(opposite(dir)));
+        if (spot == pos || level.is_open(spot)) && (behind.tile.is_solid() || behind.entity.is_some()) {
+            spots.push((spot, 0));
+        }
+    }
+    if spots.is_empty() {
+        return None;
+    }
+    if spots.iter().any(|&(spot, _)| spot == pos) {
+        return DIRECTIONS.iter().find(|&&dir| pos.step(dir) == target_pos).map(|&dir| Action::Move(dir));
+    }
+    let map = dijkstra_map(level, &spots);
+    // Dancing around the target for more than a couple of turns gives it too many free hits
+    if adjacent(pos, target_pos) && distance(&map, pos).map(|d| d > 2).unwrap_or(true) {
+        return None;
+    }
+    downhill(level, &map, pos, None).map(Action::Move)
+}
+
+// Stay just out of reach, recovering cards until the target is weak enough to finish off
+fn hang_back(level: &mut Level, pos: Position, target_pos: Position) -> Option<Action> {
+    let dist = manhattan(pos, target_pos);
+    if dist > 4 {
+        return None;
+    }
+    if dist > 2 {
+        return Some(Action::Rest);
+    }
+    let away = DIRECTIONS.iter().cloned()
+        .filter(|&dir| level.is_open(pos.step(dir)))
+        .max_by_key(|&dir| manhattan(pos.step(dir), target_pos));
+    match away {
+        Some(dir) if manhattan(pos.step(dir), target_pos) > dist => Some(Action::Move(dir)),
+        // Cornered, so it might as well fight
+        _ => None,
+    }
+}
+
+fn manhattan(a: Position, b: Position) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+fn adjacent(a: Position, b: Position) -> bool {
+    manhattan(a, b) == 1
+}
+
+fn opposite(dir: Direction) -> Direction {
+    match dir {
+        Direction::Up => Direction::Down,
+        Direction::Down => Direction::Up,
+        Direction::Left => Direction::Right,
+        Direction::Right => Direction::Left,
+    }
+}
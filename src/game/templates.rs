@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+// Compiled in copies of the rooms directory, which use the same format as an override directory
+const BUILTIN: &[(&str, &str)] = &[
+    ("lattice.txt", include_str!("../../rooms/lattice.txt")),
+    ("vaults.txt", include_str!("../../rooms/vaults.txt")),
+];
+
+const NO_ROTATION: &[usize] = &[0];
+const MIRROR: &[usize] = &[0, 1, 2, 3];
+const ALL_ROTATIONS: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7];
+
+thread_local! {
+    static TEMPLATES: RefCell<Rc<Vec<RoomTemplate>>> = RefCell::new(Rc::new(
+        BUILTIN.iter().flat_map(|&(name, text)| {
+            parse_templates(name, text).expect("built in room templates should parse")
+        }).collect()
+    ));
+}
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum RoomKind {
+    Room,
+    Start,
+    Exit,
+}
+
+#[derive(Clone)]
+pub struct RoomTemplate {
+    name: String,
+    pub kind: RoomKind,
+    pub weight: i32,
+    pub min_depth: i32,
+    pub max_depth: i32,
+    // Which of the eight ways of flipping and rotating the layout are allowed
+    pub rotations: &'static [usize],
+    width: i32,
+    height: i32,
+    cells: Vec<u8>,
+}
+
+impl RoomTemplate {
+    pub fn allowed_at(&self, depth: i32) -> bool {
+        self.weight > 0 && self.min_depth <= depth && depth <= self.max_depth
+    }
+
+    pub fn size(&self, rotation: usize) -> (i32, i32) {
+        if rotation < 4 {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        }
+    }
+
+    // The layout character at (x, y) once the rotation has been applied
+    pub fn cell(&self, rotation: usize, x: i32, y: i32) -> u8 {
+        let (w, h) = (self.width, self.height);
+        let (sx, sy) = [
+            (x, y), (w - 1 - x, y), (x, h - 1 - y), (w - 1 - x, h - 1 - y),
+            (y, x), (w - 1 - y, x), (y, h - 1 - x), (w - 1 - y, h - 1 - x),
+        ][rotation];
+        self.cells[(sy * w + sx) as usize]
+    }
+}
+
+/// Replaces the built in room templates with every `.txt` file in the given directory.
+///
+/// The templates are shared by every level generated on this thread from then on, including the
+/// later levels of a resumed save and every level of a replay being verified. Neither saves nor
+/// replays record which templates were used, so they only reproduce the same levels (and replays
+/// only verify) when loaded with the same room files.
+pub fn load_room_templates(dir: &Path) -> io::Result<()> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map(|e| e == "txt").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    // Directory order isn't stable, and the order templates are picked from matters for seeds
+    paths.sort();
+
+    let mut templates = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path)?;
+        templates.extend(parse_templates(&path.display().to_string(), &text)?);
+    }
+    for (i, template) in templates.iter().enumerate() {
+        if templates[..i].iter().any(|t| t.name == template.name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("room \"{}\" is defined more than once", template.name),
+            ));
+        }
+    }
+    TEMPLATES.with(|t| *t.borrow_mut() = Rc::new(templates));
+    Ok(())
+}
+
+pub fn room_templates() -> Rc<Vec<RoomTemplate>> {
+    TEMPLATES.with(|t| t.borrow().clone())
+}
+
+fn parse_templates(source: &str, text: &str) -> io::Result<Vec<RoomTemplate>> {
+    let error = |line: usize, msg: &str| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", source, line, msg),
+    );
+    let mut templates = Vec::new();
+    let mut current: Option<RoomTemplate> = None;
+    let mut rows: Vec<&str> = Vec::new();
+
+    // A trailing blank line finishes off the last template
+    for (i, line) in text.lines().chain(Some("")).enumerate() {
+        let number = i + 1;
+        if line.starts_with(';') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            if let Some(template) = current.take() {
+                templates.push(finish_template(template, &rows).map_err(|e| error(number, e))?);
+                rows.clear();
+            }
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let template = match current {
+            Some(ref mut template) => template,
+            None => {
+                if words.len() != 2 || words[0] != "room" {
+                    return Err(error(number, "expected \"room NAME\""));
+                }
+                current = Some(RoomTemplate {
+                    name: words[1].to_string(),
+                    kind: RoomKind::Room,
+                    weight: 1,
+                    min_depth: 0,
+                    max_depth: i32::MAX,
+                    rotations: ALL_ROTATIONS,
+                    width: 0,
+                    height: 0,
+                    cells: Vec::new(),
+                });
+                continue;
+            }
+        };
+        // Once the layout starts, everything up to the next blank line is part of it
+        if !rows.is_empty() {
+            rows.push(line);
+            continue;
+        }
+        let number_arg = |i: usize| {
+            words.get(i).and_then(|w| w.parse::<i32>().ok()).ok_or_else(|| error(number, "bad number"))
+        };
+        match (words[0], words.len()) {
+            ("kind", 2) => {
+                template.kind = match words[1] {
+                    "room" => RoomKind::Room,
+                    "start" => RoomKind::Start,
+                    "exit" => RoomKind::Exit,
+                    _ => { return Err(error(number, "unknown room kind")); }
+                };
+            }
+            ("weight", 2) => {
+                template.weight = number_arg(1)?;
+            }
+            ("depth", 2) => {
+                template.min_depth = number_arg(1)?;
+            }
+            ("depth", 3) => {
+                template.min_depth = number_arg(1)?;
+                template.max_depth = number_arg(2)?;
+            }
+            ("rotate", 2) => {
+                template.rotations = match words[1] {
+                    "none" => NO_ROTATION,
+                    "mirror" => MIRROR,
+                    "all" => ALL_ROTATIONS,
+                    _ => { return Err(error(number, "unknown rotation")); }
+                };
+            }
+            _ => { rows.push(line); }
+        }
+    }
+    Ok(templates)
+}
+
+// Short rows get padded with spaces, which leave the map alone, so trailing whitespace is optional
+fn finish_template(mut template: RoomTemplate, rows: &[&str]) -> Result<RoomTemplate, &'static str> {
+    if rows.is_empty() {
+        return Err("room has no layout");
+    }
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    for row in rows {
+        for b in row.bytes() {
+            if !b".#+>s EDHR".contains(&b) {
+                return Err("unknown character in room layout");
+            }
+        }
+        template.cells.extend(row.bytes());
+        template.cells.extend((row.len()..width).map(|_| b' '));
+    }
+    template.width = width as i32;
+    template.height = rows.len() as i32;
+    Ok(template)
+}
@@ -0,0 +1,158 @@
+// English for everything the player gets to read, so none of it leans on Debug formatting. The
+// player is always "you", and everything else gets "the" or "a" in front of its name.
+
+use super::*;
+
+pub fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// "a Block", "an Attack 2"
+pub fn a_or_an(word: &str) -> String {
+    match word.chars().next().map(|c| c.to_ascii_lowercase()) {
+        Some('a') | Some('e') | Some('i') | Some('o') | Some('u') => format!("an {}", word),
+        _ => format!("a {}", word),
+    }
+}
+
+impl EntityType {
+    pub fn name(self) -> &'static str {
+        match self {
+            EntityType::UnknownThing => "unknown thing",
+            EntityType::Player => "player",
+            EntityType::Defender => "defender",
+            EntityType::Hunter => "hunter",
+            EntityType::Reaper => "reaper",
+            EntityType::Core => "core",
+        }
+    }
+
+    // "the hunter", or "you"
+    pub fn the(self) -> String {
+        match self {
+            EntityType::Player => "you".to_string(),
+            _ => format!("the {}", self.name()),
+        }
+    }
+
+    // "a hunter", or "you"
+    pub fn a(self) -> String {
+        match self {
+            EntityType::Player => "you".to_string(),
+            _ => a_or_an(self.name()),
+        }
+    }
+
+    // "the hunter's", or "your"
+    pub fn possessive(self) -> String {
+        match self {
+            EntityType::Player => "your".to_string(),
+            _ => format!("the {}'s", self.name()),
+        }
+    }
+
+    // "its", or "your"
+    pub fn pronoun_possessive(self) -> &'static str {
+        match self {
+            EntityType::Player => "your",
+            _ => "its",
+        }
+    }
+
+    // Picks the form of a verb that goes with this as the subject, as in "you hit" but "the
+    // hunter hits"
+    pub fn verb<'a>(self, you: &'a str, it: &'a str) -> &'a str {
+        match self {
+            EntityType::Player => you,
+            _ => it,
+        }
+    }
+
+    // How to refer to `object` in a sentence this is the subject of: "you hit yourself", "the
+    // hunter hits another hunter"
+    fn object(self, object: EntityType) -> String {
+        match (self, object) {
+            (EntityType::Player, EntityType::Player) => "yourself".to_string(),
+            (a, b) if a == b => format!("another {}", b.name()),
+            (_, b) => b.the(),
+        }
+    }
+}
+
+impl Modifier {
+    pub fn name(self) -> String {
+        match self {
+            Modifier::Shield => "Shield".to_string(),
+            Modifier::Slow(n) => format!("Slow {}", n),
+            Modifier::Virus(n) => format!("Virus {}", n),
+            Modifier::Overclock => "Overclock".to_string(),
+        }
+    }
+}
+
+impl KnownCardStatus {
+    // Short enough for the deck panel
+    pub fn name(self) -> String {
+        match self {
+            KnownCardStatus::Active => "active".to_string(),
+            KnownCardStatus::Inactive => "off".to_string(),
+            KnownCardStatus::Discarded => "discarded".to_string(),
+            KnownCardStatus::PlayedOnSelf => "on you".to_string(),
+            KnownCardStatus::PlayedOnVisible(t, _) => format!("on {}", t.the()),
+            KnownCardStatus::PlayedOnOther(t) => format!("on {}", t.a()),
+        }
+    }
+}
+
+impl LogEvent {
+    pub fn text(&self) -> String {
+        match *self {
+            LogEvent::Attack(a, b, damage) => format!(
+                "{} {} {} for {} damage!",
+                capitalize(&a.the()), a.verb("attack", "attacks"), a.object(b), damage,
+            ),
+            LogEvent::Hit(a, b, damage) => format!(
+                "{} {} {} for {} damage!",
+                capitalize(&a.the()), a.verb("hit", "hits"), a.object(b), damage,
+            ),
+            LogEvent::CardActivated(t, card, discarded) => format!(
+                "({} {} card activated{})",
+                capitalize(&t.possessive()), card.name(), if discarded { " and was discarded" } else { "" },
+            ),
+            LogEvent::ModifierActivated(t, Modifier::Shield) => {
+                format!("({} Shield modifier absorbed the attack)", capitalize(&t.possessive()))
+            }
+            LogEvent::ModifierActivated(t, Modifier::Overclock) => {
+                format!("({} Overclock modifier boosted the attack)", capitalize(&t.possessive()))
+            }
+            LogEvent::ModifierActivated(t, Modifier::Slow(_)) => format!(
+                "({} {} slowed and {} {} turn)",
+                capitalize(&t.the()), t.verb("are", "is"), t.verb("lose", "loses"), t.pronoun_possessive(),
+            ),
+            LogEvent::ModifierActivated(t, Modifier::Virus(_)) => format!(
+                "({} Virus modifier corrupts {} code)",
+                capitalize(&t.possessive()), t.pronoun_possessive(),
+            ),
+            LogEvent::CardDiscarded(t, card) => {
+                format!("({} {} card was discarded by damage)", capitalize(&t.possessive()), card.name())
+            }
+            LogEvent::ModifierStripped(t, m) => {
+                format!("({} {} modifier was stripped)", capitalize(&t.possessive()), m.name())
+            }
+            LogEvent::Kill(a, b) => {
+                format!("{} {} {}!", capitalize(&a.the()), a.verb("kill", "kills"), a.object(b))
+            }
+            LogEvent::FragmentAbsorbed(card) => format!("(You absorb {} fragment)", a_or_an(&card.name())),
+            LogEvent::HiddenPassage => "You find a hidden passage!".to_string(),
+            LogEvent::LevelIntro(ref text) => text.clone(),
+            LogEvent::LevelExited => "Exiting level!".to_string(),
+            LogEvent::CoreDeleted => "The core has been deleted!".to_string(),
+            LogEvent::PlayerDeleted => "You have been deleted.".to_string(),
+            LogEvent::PressSpace => "Press [Space] to continue...".to_string(),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+// Maps key presses to game actions and UI commands. Keymaps start from one of the presets and can
+// be tweaked with a config file made up of lines like these:
+//
+//     # Comments start with a hash
+//     preset vi
+//     bind q wait
+//     bind ctrl+r rest
+//     unbind w
+//     margin 3
+//
+// `preset` throws away any earlier bindings. `margin` is how close the player can get to the edge
+// of the map view before it scrolls. Keys are single characters, `space`, `ctrl+` followed
+// by a character, or one of the key names in KEY_NAMES. Commands are the names in COMMANDS.
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use cursive::event::{Event, Key};
+
+use game::{Action, Direction};
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Command {
+    Act(Action),
+    Continue,
+    Look,
+    Scroll,
+    FocusLog,
+    FocusDeck,
+    History,
+    // Global commands work no matter which panel has focus
+    Help,
+    FocusMap,
+    Quit,
+}
+
+impl Command {
+    pub fn is_global(self) -> bool {
+        match self {
+            Command::Help | Command::FocusMap | Command::Quit => true,
+            _ => false,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        COMMANDS.iter().find(|&&(_, c, _)| c == self).map(|&(name, _, _)| name).unwrap_or("?")
+    }
+}
+
+// Also the order commands are listed in by the help
+const COMMANDS: &[(&str, Command, &str)] = &[
+    ("up", Command::Act(Action::Move(Direction::Up)), "Move or attack up"),
+    ("down", Command::Act(Action::Move(Direction::Down)), "Move or attack down"),
+    ("left", Command::Act(Action::Move(Direction::Left)), "Move or attack left"),
+    ("right", Command::Act(Action::Move(Direction::Right)), "Move or attack right"),
+    ("rest", Command::Act(Action::Rest), "Rest, recovering a discarded card"),
+    ("wait", Command::Act(Action::Wait), "Wait a turn"),
+    ("continue", Command::Continue, "Continue once the level is over"),
+    ("look", Command::Look, "Look around the map (clicking works too)"),
+    ("scroll", Command::Scroll, "Scroll the map freely with the movement keys"),
+    ("focus-log", Command::FocusLog, "Focus the message log"),
+    ("focus-deck", Command::FocusDeck, "Focus the deck"),
+    ("history", Command::History, "Show every message from the run so far"),
+    ("help", Command::Help, "Show this help"),
+    ("focus-map", Command::FocusMap, "Go back to the map"),
+    ("quit", Command::Quit, "Save and quit"),
+];
+
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("enter", Key::Enter),
+    ("esc", Key::Esc),
+    ("tab", Key::Tab),
+    ("backspace", Key::Backspace),
+    ("ins", Key::Ins),
+    ("del", Key::Del),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+    ("center", Key::NumpadCenter),
+    ("f1", Key::F1),
+    ("f2", Key::F2),
+    ("f3", Key::F3),
+    ("f4", Key::F4),
+    ("f5", Key::F5),
+    ("f6", Key::F6),
+    ("f7", Key::F7),
+    ("f8", Key::F8),
+    ("f9", Key::F9),
+    ("f10", Key::F10),
+    ("f11", Key::F11),
+    ("f12", Key::F12),
+];
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Preset {
+    Arrows,
+    Vi,
+    Wasd,
+    Numpad,
+}
+
+const PRESETS: &[(&str, Preset)] = &[
+    ("arrows", Preset::Arrows),
+    ("vi", Preset::Vi),
+    ("wasd", Preset::Wasd),
+    ("numpad", Preset::Numpad),
+];
+
+const DEFAULT_MARGIN: i32 = 5;
+
+thread_local! {
+    static KEYMAP: RefCell<Rc<Keymap>> = RefCell::new(Rc::new(Keymap::preset(Preset::Arrows)));
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: Vec<(Event, Command)>,
+    margin: i32,
+}
+
+impl Keymap {
+    pub fn preset(preset: Preset) -> Keymap {
+        let mut keymap = Keymap { bindings: Vec::new(), margin: DEFAULT_MARGIN };
+        let up = Command::Act(Action::Move(Direction::Up));
+        let down = Command::Act(Action::Move(Direction::Down));
+        let left = Command::Act(Action::Move(Direction::Left));
+        let right = Command::Act(Action::Move(Direction::Right));
+        let rest = Command::Act(Action::Rest);
+        let wait = Command::Act(Action::Wait);
+
+        // Arrow keys work in every preset
+        keymap.bind(Key::Up.into(), up);
+        keymap.bind(Key::Down.into(), down);
+        keymap.bind(Key::Left.into(), left);
+        keymap.bind(Key::Right.into(), right);
+        let extra = match preset {
+            Preset::Arrows => vec![('.', rest), ('r', rest), ('w', wait), ('l', Command::Look)],
+            Preset::Vi => vec![
+                ('k', up), ('j', down), ('h', left), ('l', right),
+                ('.', rest), ('r', rest), ('w', wait), ('x', Command::Look),
+            ],
+            Preset::Wasd => vec![
+                ('w', up), ('s', down), ('a', left), ('d', right),
+                ('.', rest), ('r', rest), ('e', wait), ('l', Command::Look),
+            ],
+            Preset::Numpad => vec![
+                ('8', up), ('2', down), ('4', left), ('6', right),
+                ('0', rest), ('.', rest), ('r', rest), ('5', wait), ('l', Command::Look),
+            ],
+        };
+        for (ch, command) in extra {
+            keymap.bind(Event::Char(ch), command);
+        }
+        if preset == Preset::Numpad {
+            keymap.bind(Key::NumpadCenter.into(), wait);
+        }
+        keymap.bind(Event::Char(' '), Command::Continue);
+        keymap.bind(Event::Char('v'), Command::Scroll);
+        keymap.bind(Event::Char('m'), Command::FocusLog);
+        keymap.bind(Event::Char('i'), Command::FocusDeck);
+        keymap.bind(Event::Char('M'), Command::History);
+
+        keymap.bind(Event::Char('?'), Command::Help);
+        keymap.bind(Key::Esc.into(), Command::FocusMap);
+        keymap.bind(Event::Char(' '), Command::FocusMap);
+        keymap.bind(Event::CtrlChar('q'), Command::Quit);
+        keymap
+    }
+
+    /// Binds the key to the command, replacing whatever the key did before in the same context (on
+    /// the map for most commands, everywhere for global ones).
+    pub fn bind(&mut self, event: Event, command: Command) {
+        let global = command.is_global();
+        self.bindings.retain(|&(ref e, c)| *e != event || c.is_global() != global);
+        self.bindings.push((event, command));
+    }
+
+    pub fn unbind(&mut self, event: &Event) {
+        self.bindings.retain(|&(ref e, _)| e != event);
+    }
+
+    /// What the key does when pressed on the map.
+    pub fn map_command(&self, event: &Event) -> Option<Command> {
+        self.bindings.iter()
+            .find(|&&(ref e, c)| e == event && !c.is_global())
+            .map(|&(_, c)| c)
+    }
+
+    pub fn margin(&self) -> i32 {
+        self.margin
+    }
+
+    pub fn global_bindings(&self) -> Vec<(Event, Command)> {
+        self.bindings.iter().filter(|&&(_, c)| c.is_global()).cloned().collect()
+    }
+
+    /// The keys bound to each command along with a description of it, in the order the help lists
+    /// them.
+    pub fn help(&self, global: bool) -> Vec<(Vec<String>, &'static str)> {
+        COMMANDS.iter().filter(|&&(_, c, _)| c.is_global() == global).filter_map(|&(_, c, help)| {
+            let keys: Vec<_> = self.bindings.iter()
+                .filter(|&&(_, bound)| bound == c)
+                .map(|&(ref e, _)| describe_event(e))
+                .collect();
+            if keys.is_empty() { None } else { Some((keys, help)) }
+        }).collect()
+    }
+
+    /// Bindings that can't work as intended, because the deck panel keeps the letters to itself
+    /// for examining and toggling cards, and the map keeps its own keys.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for &(ref event, command) in &self.bindings {
+            if !command.is_global() {
+                continue;
+            }
+            if let Event::Char(ch) = *event {
+                if ch.is_ascii_alphabetic() {
+                    conflicts.push(format!(
+                        "{} is bound to {}, but the deck panel uses letters to examine and toggle \
+                         cards", describe_event(event), command.name(),
+                    ));
+                }
+            }
+            // Going back to the map from the map doing something else is harmless
+            if command == Command::FocusMap {
+                continue;
+            }
+            if let Some(shadow) = self.map_command(event) {
+                conflicts.push(format!(
+                    "{} is bound to both {} and {}, so {} won't work from the map",
+                    describe_event(event), shadow.name(), command.name(), command.name(),
+                ));
+            }
+        }
+        conflicts
+    }
+}
+
+pub fn describe_event(event: &Event) -> String {
+    match *event {
+        Event::Char(' ') => "Space".to_string(),
+        Event::Char(ch) => ch.to_string(),
+        Event::CtrlChar(ch) => format!("Ctrl+{}", ch),
+        Event::Key(key) => format!("{:?}", key),
+        ref e => format!("{:?}", e),
+    }
+}
+
+fn parse_key(name: &str) -> Option<Event> {
+    let lower = name.to_lowercase();
+    if lower == "space" {
+        return Some(Event::Char(' '));
+    }
+    if lower.starts_with("ctrl+") {
+        let mut chars = name["ctrl+".len()..].chars();
+        return match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(Event::CtrlChar(ch.to_ascii_lowercase())),
+            _ => None,
+        };
+    }
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(Event::Char(ch));
+    }
+    KEY_NAMES.iter().find(|&&(n, _)| n == lower).map(|&(_, key)| Event::Key(key))
+}
+
+fn parse_keymap(source: &str, text: &str) -> io::Result<Keymap> {
+    let error = |line: usize, msg: &str| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", source, line, msg),
+    );
+    let mut keymap = Keymap::preset(Preset::Arrows);
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first().map(|w| w.starts_with('#')).unwrap_or(true) {
+            continue;
+        }
+        match (words[0], words.len()) {
+            ("preset", 2) => match PRESETS.iter().find(|&&(n, _)| n == words[1]) {
+                Some(&(_, preset)) => {
+                    keymap = Keymap { margin: keymap.margin, ..Keymap::preset(preset) };
+                }
+                None => { return Err(error(i + 1, &format!("unknown preset \"{}\"", words[1]))); }
+            },
+            ("bind", 3) => {
+                let (key, command) = (words[1], words[2]);
+                let event = parse_key(key)
+                    .ok_or_else(|| error(i + 1, &format!("unknown key \"{}\"", key)))?;
+                let command = COMMANDS.iter().find(|&&(n, _, _)| n == command)
+                    .map(|&(_, c, _)| c)
+                    .ok_or_else(|| error(i + 1, &format!("unknown command \"{}\"", command)))?;
+                keymap.bind(event, command);
+            }
+            ("unbind", 2) => {
+                let event = parse_key(words[1])
+                    .ok_or_else(|| error(i + 1, &format!("unknown key \"{}\"", words[1])))?;
+                keymap.unbind(&event);
+            }
+            ("margin", 2) => {
+                keymap.margin = match words[1].parse() {
+                    Ok(margin) if margin >= 0 => margin,
+                    _ => { return Err(error(i + 1, "expected a margin of 0 or more")); }
+                };
+            }
+            _ => { return Err(error(i + 1, "expected preset, bind, unbind or margin")); }
+        }
+    }
+    Ok(keymap)
+}
+
+/// Replaces the keymap with the one described by the given config file, refusing any that has
+/// conflicting bindings.
+pub fn load_keymap(path: &Path) -> io::Result<()> {
+    let keymap = parse_keymap(&path.display().to_string(), &fs::read_to_string(path)?)?;
+    let conflicts = keymap.conflicts();
+    if !conflicts.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, conflicts.join("; ")));
+    }
+    KEYMAP.with(|k| *k.borrow_mut() = Rc::new(keymap));
+    Ok(())
+}
+
+pub fn keymap() -> Rc<Keymap> {
+    KEYMAP.with(|k| k.borrow().clone())
+}
@@ -2,9 +2,104 @@ extern crate cursive;
 extern crate cyberphage;
 extern crate rand;
 
+use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use cyberphage::game::{Replay, DEFAULT_CAMPAIGN_LENGTH};
+
+const SAVE_FILE: &str = "cyberphage.sav";
+// Loaded if it exists and no other keymap was given
+const KEYS_FILE: &str = "cyberphage.keys";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: cyberphage [--rooms DIR] [--keys FILE] [--levels N] [--replay FILE | --verify FILE]"
+    );
+    process::exit(2);
+}
+
+enum Mode {
+    Play,
+    Replay(String),
+    Verify(String),
+}
+
 fn main() {
-    let siv = &mut cursive::Cursive::default();
-    let seed: u32 = rand::random();
-    cyberphage::build_ui(siv, seed);
-    siv.run();
+    let mut rooms = None;
+    let mut keys = None;
+    // Only used for new games, since saves and replays know their own length
+    let mut levels = DEFAULT_CAMPAIGN_LENGTH;
+    let mut mode = Mode::Play;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match (&*arg, &mode) {
+            ("--rooms", _) => rooms = Some(value()),
+            ("--keys", _) => keys = Some(value()),
+            ("--levels", _) => levels = match value().parse() {
+                Ok(n) if n >= 1 => n,
+                _ => usage(),
+            },
+            ("--replay", &Mode::Play) => mode = Mode::Replay(value()),
+            ("--verify", &Mode::Play) => mode = Mode::Verify(value()),
+            ("--replay", _) | ("--verify", _) => usage(),
+            (arg, _) => {
+                eprintln!("unknown option {}", arg);
+                usage();
+            }
+        }
+    }
+
+    if let Some(dir) = rooms {
+        if let Err(e) = cyberphage::game::load_room_templates(Path::new(&dir)) {
+            eprintln!("{}: {}", dir, e);
+            process::exit(2);
+        }
+    }
+    let keys = keys.or_else(|| {
+        if Path::new(KEYS_FILE).exists() {
+            Some(KEYS_FILE.to_string())
+        } else {
+            None
+        }
+    });
+    if let Some(path) = keys {
+        if let Err(e) = cyberphage::load_keymap(Path::new(&path)) {
+            eprintln!("{}: {}", path, e);
+            process::exit(2);
+        }
+    }
+    match mode {
+        Mode::Verify(path) => {
+            match File::open(&path).and_then(|mut f| Replay::load(&mut f)).map(|r| r.verify()) {
+                Ok(true) => println!("{}: ok", path),
+                Ok(false) => {
+                    println!("{}: final state does not match", path);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    process::exit(2);
+                }
+            }
+        }
+        Mode::Replay(path) => {
+            let mut siv = cursive::Cursive::default();
+            if let Err(e) = cyberphage::build_replay_ui(&mut siv, Path::new(&path)) {
+                // Restore the terminal before complaining
+                drop(siv);
+                eprintln!("{}: {}", path, e);
+                process::exit(2);
+            }
+            siv.run();
+        }
+        Mode::Play => {
+            let siv = &mut cursive::Cursive::default();
+            let seed: u32 = rand::random();
+            cyberphage::build_ui_with_save(siv, seed, levels, PathBuf::from(SAVE_FILE));
+            siv.run();
+        }
+    }
 }
@@ -0,0 +1,1273 @@
+use std::cell::{Cell, RefCell};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cursive::views::*;
+use cursive::{
+    Cursive,
+    Printer,
+    align::HAlign,
+    direction::{Absolute, Direction, Orientation},
+    event::{Event, EventResult, Key, MouseButton, MouseEvent},
+    theme::ColorStyle,
+    utils::markup::StyledString,
+    vec::Vec2,
+    view::{Identifiable, ScrollStrategy, View, ViewWrapper},
+};
+use rand;
+
+use colors;
+use game;
+use keymap::{self, Command};
+
+const MAP_ID: &str = "map";
+const MAIN_PANEL_ID: &str = "main_panel";
+const STATUS_ID: &str = "status";
+
+const CARDS_ID: &str = "cards";
+const CARDS_DIALOG_ID: &str = "cards_dialog";
+
+const INFO_ID: &str = "info";
+const INFO_DIALOG_ID: &str = "info_dialog";
+
+const HELP_ID: &str = "help";
+const HELP_DIALOG_ID: &str = "help_dialog";
+
+const HISTORY_ID: &str = "history";
+const HISTORY_DIALOG_ID: &str = "history_dialog";
+const HISTORY_SEARCH_ID: &str = "history_search";
+const HISTORY_EXPORT_FILE: &str = "cyberphage-log.txt";
+
+const QUIT_ID: &str = "quit";
+const BUTTONS_ID: &str = "buttons";
+
+const INFO_HEIGHT: usize = 8;
+
+// Health at or below this shows up in red on the status line
+const LOW_HEALTH: i32 = 2;
+
+// Names for the kinds of log entry, in the order of the number keys that show and hide them
+const LOG_KIND_NAMES: &[(game::LogKind, &str)] = &[
+    (game::LogKind::Attack, "attacks"),
+    (game::LogKind::Damage, "damage"),
+    (game::LogKind::CardActivated, "cards used"),
+    (game::LogKind::CardDiscarded, "cards lost"),
+    (game::LogKind::Kill, "kills"),
+    (game::LogKind::LevelEvent, "level events"),
+    (game::LogKind::System, "system messages"),
+];
+
+thread_local! {
+    // Kinds of log entry currently hidden from the log panel
+    static HIDDEN_LOG_KINDS: RefCell<Vec<game::LogKind>> = RefCell::new(Vec::new());
+}
+
+const REPLAY_FPS: u32 = 30;
+const REPLAY_DEFAULT_DELAY: u32 = 4;
+const REPLAY_MAX_DELAY: u32 = 64;
+
+struct IsolateFocusView<T: View> {
+    view: T,
+}
+
+impl<T: View> IsolateFocusView<T> {
+    fn new(view: T) -> Self {
+        Self { view }
+    }
+}
+
+impl<T: View> ViewWrapper for IsolateFocusView<T> {
+    wrap_impl!(self.view: T);
+
+    fn wrap_take_focus(&mut self, source: Direction) -> bool {
+        match source {
+            // TODO: really return true?
+            Direction::Abs(Absolute::None) => { self.view.take_focus(source); true },
+            _ => false,
+        }
+    }
+}
+
+// State behind the map canvas. The cursor is only there while looking around.
+struct MapState {
+    game: Rc<RefCell<game::Recorder>>,
+    cursor: Option<game::Position>,
+    camera: Camera,
+}
+
+// Which part of the map is on screen. It follows the player (or the look cursor) around unless it's
+// been let loose to scroll freely.
+struct Camera {
+    // The map position shown in the top left corner of the view
+    corner: Cell<game::Position>,
+    // How big the view was when it was last drawn
+    size: Cell<Vec2>,
+    free: bool,
+}
+
+impl Camera {
+    fn new() -> Camera {
+        Camera {
+            corner: Cell::new(game::Position { x: 0, y: 0 }),
+            size: Cell::new(Vec2::new(0, 0)),
+            free: false,
+        }
+    }
+}
+
+// Moves the edge of the view along one axis just enough to keep the focus at least `margin` cells
+// away from it
+fn follow_axis(corner: i32, focus: i32, view: i32, margin: i32) -> i32 {
+    let margin = margin.min((view - 1) / 2).max(0);
+    if focus - corner < margin {
+        focus - margin
+    } else if focus - corner > view - 1 - margin {
+        focus - (view - 1 - margin)
+    } else {
+        corner
+    }
+}
+
+// Keeps the view inside the map, centering maps smaller than the view
+fn clamp_axis(corner: i32, view: i32, size: i32) -> i32 {
+    if size <= view {
+        -(view - size) / 2
+    } else {
+        corner.max(0).min(size - view)
+    }
+}
+
+fn set_log_title(s: &mut Cursive, title: &str) {
+    s.call_on_id(INFO_DIALOG_ID, |view: &mut Dialog| view.set_title(title));
+}
+
+fn log_title() -> String {
+    let hidden = HIDDEN_LOG_KINDS.with(|h| h.borrow().len());
+    if hidden == 0 {
+        "Log".to_string()
+    } else {
+        format!("Log ({} of {} kinds hidden)", hidden, LOG_KIND_NAMES.len())
+    }
+}
+
+fn toggle_log_kind(s: &mut Cursive, kind: game::LogKind, game: &game::Game) {
+    HIDDEN_LOG_KINDS.with(|h| {
+        let mut hidden = h.borrow_mut();
+        match hidden.iter().position(|&k| k == kind) {
+            Some(i) => { hidden.remove(i); }
+            None => hidden.push(kind),
+        }
+    });
+    set_log_title(s, &log_title());
+    update_ui(s, game);
+}
+
+// A run of identical log lines, shown once with a count
+struct LogLine {
+    depth: i32,
+    turn: u32,
+    kind: game::LogKind,
+    text: String,
+    count: usize,
+}
+
+// The log as the player gets to see it: only what they saw happen, minus any hidden kinds, with
+// repeats collapsed into one line
+fn log_lines(entries: &[game::LogEntry], hidden: &[game::LogKind]) -> Vec<LogLine> {
+    let mut lines: Vec<LogLine> = Vec::new();
+    for entry in entries {
+        let kind = entry.kind();
+        if !entry.seen || hidden.contains(&kind) {
+            continue;
+        }
+        let text = entry.text();
+        if let Some(last) = lines.last_mut() {
+            if last.depth == entry.depth && last.kind == kind && last.text == text {
+                last.turn = entry.turn;
+                last.count += 1;
+                continue;
+            }
+        }
+        lines.push(LogLine { depth: entry.depth, turn: entry.turn, kind, text, count: 1 });
+    }
+    lines
+}
+
+fn styled_log(lines: &[LogLine]) -> StyledString {
+    let mut text = StyledString::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            text.append_plain("\n");
+            if lines[i - 1].turn != line.turn {
+                text.append_plain("---\n");
+            }
+        }
+        text.append_styled(line.text.clone(), colors::log_style(line.kind));
+        if line.count > 1 {
+            text.append_plain(format!(" x{}", line.count));
+        }
+    }
+    text
+}
+
+// The history screen works off a copy of the run's log taken when it was opened
+struct HistoryState {
+    entries: Vec<game::LogEntry>,
+    search: String,
+    hidden: Vec<game::LogKind>,
+}
+
+impl HistoryState {
+    fn lines(&self) -> Vec<LogLine> {
+        let search = self.search.to_lowercase();
+        log_lines(&self.entries, &self.hidden).into_iter()
+            .filter(|line| line.text.to_lowercase().contains(&search))
+            .collect()
+    }
+
+    fn title(&self) -> String {
+        let mut title = "History".to_string();
+        if !self.hidden.is_empty() {
+            let (hidden, kinds) = (self.hidden.len(), LOG_KIND_NAMES.len());
+            title.push_str(&format!(" ({} of {} kinds hidden)", hidden, kinds));
+        }
+        title
+    }
+}
+
+fn history_line(line: &LogLine) -> String {
+    let mut text = format!("{:>5} {:>6}  {}", line.depth, line.turn, line.text);
+    if line.count > 1 {
+        text.push_str(&format!(" x{}", line.count));
+    }
+    text
+}
+
+fn refresh_history(s: &mut Cursive, state: &HistoryState) {
+    let lines = state.lines();
+    s.call_on_id(HISTORY_ID, |view: &mut SelectView<u32>| {
+        view.clear();
+        for line in &lines {
+            view.add_item(history_line(line), line.turn);
+        }
+        if !lines.is_empty() {
+            view.set_selection(lines.len() - 1);
+        }
+    });
+    s.call_on_id(HISTORY_DIALOG_ID, |view: &mut Dialog| view.set_title(state.title()));
+}
+
+// Asks for a line of text, calling back with it once it's entered
+fn prompt<F: Fn(&mut Cursive, &str) + 'static>(
+    s: &mut Cursive,
+    title: &str,
+    initial: &str,
+    on_submit: F,
+) {
+    s.add_layer(
+        Dialog::around(BoxView::with_fixed_width(
+            30,
+            EditView::new().content(initial).on_submit(move |s, text| {
+                s.pop_layer();
+                on_submit(s, text);
+            }),
+        ))
+            .title(title)
+            .dismiss_button("Cancel")
+    );
+}
+
+fn jump_to_turn(s: &mut Cursive, state: &HistoryState, text: &str) {
+    let turn: u32 = match text.trim().parse() {
+        Ok(turn) => turn,
+        Err(_) => {
+            s.add_layer(Dialog::info(format!("{:?} isn't a turn number.", text.trim())));
+            return;
+        }
+    };
+    let lines = state.lines();
+    // Collapsed lines are filed under the last turn they cover, so the first one at or after the
+    // turn is where it starts
+    let index = match lines.iter().position(|line| line.turn >= turn) {
+        Some(index) => index,
+        None => { return; }
+    };
+    s.call_on_id(HISTORY_ID, |view: &mut SelectView<u32>| {
+        view.set_selection(index);
+    });
+    s.focus_id(HISTORY_ID).unwrap();
+}
+
+fn export_history(path: &str, lines: &[LogLine]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{:>5} {:>6}  {}", "Level", "Turn", "Message")?;
+    for line in lines {
+        writeln!(file, "{}", history_line(line))?;
+    }
+    Ok(())
+}
+
+// Every message from the run so far on one screen, searchable, filterable by kind (with the same
+// number keys as the log panel, but separately) and exportable to a text file
+fn show_history(s: &mut Cursive, game: &game::Game) {
+    if s.call_on_id(HISTORY_ID, |_: &mut SelectView<u32>| ()).is_some() {
+        return;
+    }
+    let state = Rc::new(RefCell::new(HistoryState {
+        entries: game.log_entries().to_vec(),
+        search: String::new(),
+        hidden: Vec::new(),
+    }));
+    let jump = {
+        let state = state.clone();
+        move |s: &mut Cursive| {
+            let state = state.clone();
+            prompt(s, "Go to turn", "", move |s, text| jump_to_turn(s, &state.borrow(), text));
+        }
+    };
+    let export = {
+        let state = state.clone();
+        move |s: &mut Cursive| {
+            let state = state.clone();
+            prompt(s, "Export to file", HISTORY_EXPORT_FILE, move |s, path| {
+                let lines = state.borrow().lines();
+                s.add_layer(Dialog::info(match export_history(path, &lines) {
+                    Ok(()) => format!("Saved {} lines to {}.", lines.len(), path),
+                    Err(e) => format!("Unable to save {}: {}", path, e),
+                }));
+            });
+        }
+    };
+    let search = {
+        let state = state.clone();
+        move |s: &mut Cursive, text: &str, _: usize| {
+            state.borrow_mut().search = text.to_string();
+            refresh_history(s, &state.borrow());
+        }
+    };
+
+    let mut view = OnEventView::new(
+        Dialog::around(
+            LinearLayout::new(Orientation::Vertical)
+                .child(
+                    LinearLayout::new(Orientation::Horizontal)
+                        .child(TextView::new("Search: "))
+                        .child(BoxView::with_full_width(
+                            EditView::new().on_edit(search).with_id(HISTORY_SEARCH_ID)
+                        ))
+                )
+                .child(DummyView)
+                .child(TextView::new(StyledString::single_span(
+                    format!("{:>5} {:>6}  {}", "Level", "Turn", "Message"),
+                    ColorStyle::secondary().into(),
+                )))
+                .child(BoxView::with_full_screen(SelectView::<u32>::new().with_id(HISTORY_ID)))
+        )
+            .title("History")
+            .title_position(HAlign::Left)
+            .button("Go to turn", jump.clone())
+            .button("Export", export.clone())
+            .button("Close", |s| { s.pop_layer(); })
+            .with_id(HISTORY_DIALOG_ID)
+    )
+        .on_event('/', |s| s.focus_id(HISTORY_SEARCH_ID).unwrap())
+        .on_event('t', jump)
+        .on_event('e', export)
+        .on_event(Key::Esc, |s| { s.pop_layer(); });
+    for (i, &(kind, _)) in LOG_KIND_NAMES.iter().enumerate() {
+        let state = state.clone();
+        view = view.on_event((b'1' + i as u8) as char, move |s| {
+            {
+                let mut state = state.borrow_mut();
+                match state.hidden.iter().position(|&k| k == kind) {
+                    Some(i) => { state.hidden.remove(i); }
+                    None => state.hidden.push(kind),
+                }
+            }
+            refresh_history(s, &state.borrow());
+        });
+    }
+    s.add_fullscreen_layer(BoxView::with_full_screen(view));
+    refresh_history(s, &state.borrow());
+    s.focus_id(HISTORY_ID).unwrap();
+}
+
+fn status_line(level: &game::Level) -> StyledString {
+    let status = level.player_status();
+    let mut text = StyledString::plain(format!(
+        "Level {}{}  Turn {}  ",
+        level.depth(), if level.is_final() { " (final)" } else { "" }, level.turn(),
+    ));
+    let health = format!("Health {}", status.health);
+    if status.health <= LOW_HEALTH {
+        text.append_styled(health, colors::DANGER);
+    } else {
+        text.append_plain(health);
+    }
+    text.append_plain(format!(
+        "  Hand {}/{}  Discarded {}", status.hand, status.deck, status.discarded,
+    ));
+    if status.played > 0 {
+        text.append_plain(format!("  In play {}", status.played));
+    }
+    if status.modifiers > 0 {
+        let mods = level.player_modifiers().iter().map(|mv| mv.modifier.name()).collect::<Vec<_>>();
+        text.append_plain(format!("  Modifiers: {}", mods.join(", ")));
+    }
+    if status.on_exit {
+        text.append_styled("  On the exit", colors::EXIT);
+    }
+    text
+}
+
+fn update_ui(s: &mut Cursive, game: &game::Game) {
+    let status = match *game {
+        game::Game::Level(ref level) => status_line(level),
+        game::Game::Transition(ref trans) => {
+            StyledString::plain(format!("Mutating before level {}", trans.next_level()))
+        }
+        game::Game::GameOver(ref death, _) => StyledString::plain(
+            format!("Deleted on level {} after {} turns", death.level, death.turns)
+        ),
+        game::Game::Victory(_) => StyledString::plain("Core deleted"),
+    };
+    s.call_on_id(STATUS_ID, |view: &mut TextView| view.set_content(status));
+    match *game {
+        game::Game::Level(ref level) => {
+            // The panel only keeps to the current level, the history screen has the rest
+            let entries = level.log_entries();
+            let start = entries.iter().position(|e| e.depth == level.depth())
+                .unwrap_or(entries.len());
+            let lines = HIDDEN_LOG_KINDS.with(|h| log_lines(&entries[start..], &h.borrow()));
+            s.call_on_id(INFO_ID, |view: &mut TextView| {
+                view.set_content(styled_log(&lines));
+            });
+            s.call_on_id(CARDS_ID, |view: &mut TextView| {
+                let mut content = level.player_deck().into_iter().enumerate().map(|(i, cv)| {
+                    let key = (i as u8 + b'a') as char;
+                    format!("{}] {} ({})\n", key, cv.card.name(), cv.status.name())
+                }).collect::<String>();
+                let mods = level.player_modifiers();
+                if !mods.is_empty() {
+                    content.push_str("\nModifiers:\n");
+                    for mv in mods {
+                        content.push_str(
+                            &format!("  {} (from {})\n", mv.modifier.name(), mv.source.a())
+                        );
+                    }
+                }
+                if !level.collected().is_empty() {
+                    content.push_str("\nAbsorbed:\n");
+                    for (card, count) in level.collected() {
+                        content.push_str(&format!("  {} x{}\n", card.name(), count));
+                    }
+                }
+                view.set_content(content);
+            });
+        }
+        game::Game::Transition(ref trans) => {
+            s.call_on_id(INFO_ID, |view: &mut TextView| {
+                view.set_content(format!("Mutating before level {}...", trans.next_level()));
+            });
+            s.call_on_id(CARDS_ID, |view: &mut TextView| {
+                view.set_content(
+                    trans.deck().iter().map(|card| format!("{}\n", card.name())).collect::<String>()
+                );
+            });
+        }
+        game::Game::GameOver(ref death, _) => {
+            s.call_on_id(INFO_ID, |view: &mut TextView| {
+                view.set_content(format!("{}.", death.cause));
+            });
+            s.call_on_id(CARDS_ID, |view: &mut TextView| {
+                view.set_content("");
+            });
+        }
+        game::Game::Victory(_) => {
+            s.call_on_id(INFO_ID, |view: &mut TextView| {
+                view.set_content("The core has been deleted. You won!");
+            });
+            s.call_on_id(CARDS_ID, |view: &mut TextView| {
+                view.set_content("");
+            });
+        }
+    };
+}
+
+fn controls_help() -> String {
+    let keymap = keymap::keymap();
+    let mut text = "On the map:\n".to_string();
+    for (keys, help) in keymap.help(false) {
+        text.push_str(&format!("  {:<22} {}\n", keys.join(", "), help));
+    }
+    text.push_str(&format!(
+        "\nIn the deck:\n  {:<22} {}\n  {:<22} {}\n",
+        "a-z", "Examine a card",
+        "A-Z", "Toggle a card on or off",
+    ));
+    let kind_keys = format!("1-{}", LOG_KIND_NAMES.len());
+    text.push_str(&format!("\nIn the log:\n  {:<22} Show or hide ", kind_keys));
+    text.push_str(&LOG_KIND_NAMES.iter().map(|&(_, name)| name).collect::<Vec<_>>().join(", "));
+    text.push_str("\n");
+    text.push_str(&format!(
+        "\nIn the history:\n  {:<22} {}\n  {:<22} {}\n  {:<22} {}\n  {:<22} {}\n  {:<22} {}\n",
+        kind_keys, "Show or hide a kind of message, as in the log",
+        "/", "Search",
+        "t", "Go to a turn",
+        "e", "Export the messages shown to a text file",
+        "Esc", "Close",
+    ));
+    text.push_str(&format!(
+        "\nWhile looking:\n  {:<22} {}\n  {:<22} {}\n",
+        "Movement keys", "Move the cursor",
+        "Look, Esc, Enter", "Stop looking",
+    ));
+    text.push_str(&format!(
+        "\nWhile scrolling:\n  {:<22} {}\n  {:<22} {}\n",
+        "Movement keys", "Move the view",
+        "Scroll, Esc, Enter", "Go back to following the player",
+    ));
+    text.push_str("\nAnywhere:\n");
+    for (keys, help) in keymap.help(true) {
+        text.push_str(&format!("  {:<22} {}\n", keys.join(", "), help));
+    }
+    text
+}
+
+const CARDS_HELP: &str = "\
+Your deck is your health. Every card in your hand is a point of health, and so is every modifier \
+on you.
+
+Whenever something happens to you (attacking, being attacked, moving, resting, waiting), your \
+cards are shuffled and checked one by one, and the first active card that applies triggers. At \
+most one card triggers per event.
+
+Damage that gets through discards a random card from your hand (or strips a random modifier) per \
+point. Taking damage with nothing left in hand and no modifiers deletes you.
+
+Some cards discard themselves when they trigger. Others get played on yourself or the target as \
+modifiers, and go to the discard pile once the modifier wears off.
+
+Resting recovers one random card from your discard pile. Recovering a card that was played as a \
+modifier pulls the modifier back off.
+
+Toggled off cards stay in your hand, but never trigger.
+
+Enemies killed leave a fragment of their code behind. Absorb fragments to add or upgrade cards \
+between levels. Examine a card in the deck for the details of what it does.";
+
+fn enemies_help() -> String {
+    let types = [
+        game::EntityType::Player,
+        game::EntityType::Defender,
+        game::EntityType::Hunter,
+        game::EntityType::Reaper,
+        game::EntityType::Core,
+    ];
+    let mut text = String::new();
+    for &typ in &types {
+        let name = game::capitalize(typ.name());
+        text.push_str(&format!("{}  {}\n   {}\n\n", typ.render(), name, typ.description()));
+    }
+    text.push_str("Enemies that lose most of their hand run off to rest before coming back.");
+    text
+}
+
+fn show_help(s: &mut Cursive) {
+    if s.call_on_id(HELP_ID, |_: &mut TextView| ()).is_some() {
+        return;
+    }
+    let page = |title: &'static str, text: String| move |s: &mut Cursive| {
+        s.call_on_id(HELP_ID, |view: &mut TextView| view.set_content(text.clone()));
+        s.call_on_id(HELP_DIALOG_ID, |view: &mut Dialog| view.set_title(title));
+    };
+    s.add_layer(
+        Dialog::around(BoxView::with_max_width(80, TextView::new(controls_help()).with_id(HELP_ID)))
+            .title("Controls")
+            .button("Controls", page("Controls", controls_help()))
+            .button("Cards", page("Cards", CARDS_HELP.to_string()))
+            .button("Enemies", page("Enemies", enemies_help()))
+            .dismiss_button("Close")
+            .with_id(HELP_DIALOG_ID)
+    );
+}
+
+fn process_map_event(state: &mut MapState, event: Event) -> EventResult {
+    if state.cursor.is_some() {
+        return process_look_event(state, event);
+    }
+    if state.camera.free {
+        return process_scroll_event(state, event);
+    }
+    let action_cb = move |game: Rc<RefCell<game::Recorder>>, action| EventResult::with_cb(move |s| {
+        let mut game = game.borrow_mut();
+        let updated = game.step(action);
+        update_ui(s, game.game());
+        // TODO: make less terrible?
+        if updated {
+            let size = s.screen_size();
+            s.screen_mut().layout(size);
+            s.call_on_id(INFO_ID, |view: &mut TextView| {
+                view.scroll_bottom();
+            });
+        }
+    });
+    let game = state.game.clone();
+    if let Some(pos) = clicked_position(state, &event) {
+        return look_at(state, pos);
+    }
+    let command = match keymap::keymap().map_command(&event) {
+        Some(command) => command,
+        None => { return EventResult::Ignored; }
+    };
+    match command {
+        Command::Act(action) => action_cb(game, action),
+        Command::Continue => EventResult::with_cb(move |s| {
+            {
+                let mut game = game.borrow_mut();
+                game.update();
+                update_ui(s, game.game());
+            }
+            show_game_over(s, &game);
+            show_draft(s, &game);
+        }),
+        Command::Look => {
+            let pos = match game.borrow().game().level().and_then(|l| l.player_position()) {
+                Some(pos) => pos,
+                None => { return EventResult::Ignored; }
+            };
+            look_at(state, pos)
+        }
+        Command::Scroll => {
+            state.camera.free = true;
+            EventResult::with_cb(|s| set_log_title(s, "Log (scrolling, [Esc] to stop)"))
+        }
+        Command::FocusLog => EventResult::with_cb(|s| s.focus_id(INFO_ID).unwrap()),
+        Command::FocusDeck => EventResult::with_cb(|s| s.focus_id(CARDS_ID).unwrap()),
+        Command::History => EventResult::with_cb(move |s| show_history(s, game.borrow().game())),
+        Command::Help | Command::FocusMap | Command::Quit => EventResult::Ignored,
+    }
+}
+
+fn clicked_position(state: &MapState, event: &Event) -> Option<game::Position> {
+    let corner = state.camera.corner.get();
+    match *event {
+        Event::Mouse { offset, position, event: MouseEvent::Press(MouseButton::Left) } => {
+            position.checked_sub(offset).map(|p| game::Position {
+                x: corner.x + p.x as i32,
+                y: corner.y + p.y as i32,
+            })
+        }
+        _ => None,
+    }
+}
+
+// Free scrolling pans the view around with the movement keys, leaving the player wherever they are
+fn process_scroll_event(state: &mut MapState, event: Event) -> EventResult {
+    let command = match event {
+        Event::Key(Key::Esc) | Event::Key(Key::Enter) => Some(Command::Scroll),
+        _ => keymap::keymap().map_command(&event),
+    };
+    match command {
+        Some(Command::Act(game::Action::Move(dir))) => {
+            let delta = game::Position { x: 0, y: 0 }.step(dir);
+            let size = state.camera.size.get();
+            let corner = state.camera.corner.get();
+            state.camera.corner.set(game::Position {
+                x: corner.x + delta.x * (size.x as i32 / 4).max(1),
+                y: corner.y + delta.y * (size.y as i32 / 4).max(1),
+            });
+            EventResult::Consumed(None)
+        }
+        Some(Command::Scroll) => {
+            state.camera.free = false;
+            EventResult::with_cb(|s| set_log_title(s, &log_title()))
+        }
+        _ => EventResult::Ignored,
+    }
+}
+
+// Look mode takes over the arrow keys to move the cursor around, describing whatever is under it
+// in the log panel until it's closed again
+fn process_look_event(state: &mut MapState, event: Event) -> EventResult {
+    let cursor = match state.cursor {
+        Some(pos) => pos,
+        None => { return EventResult::Ignored; }
+    };
+    if let Some(pos) = clicked_position(state, &event) {
+        return look_at(state, pos);
+    }
+    let command = match event {
+        Event::Key(Key::Esc) | Event::Key(Key::Enter) => Some(Command::Look),
+        _ => keymap::keymap().map_command(&event),
+    };
+    let dir = match command {
+        Some(Command::Act(game::Action::Move(dir))) => dir,
+        Some(Command::Look) => {
+            state.cursor = None;
+            let game = state.game.clone();
+            return EventResult::with_cb(move |s| {
+                set_log_title(s, &log_title());
+                update_ui(s, game.borrow().game());
+            });
+        }
+        _ => { return EventResult::Ignored; }
+    };
+    look_at(state, cursor.step(dir))
+}
+
+fn look_at(state: &mut MapState, pos: game::Position) -> EventResult {
+    let text = match state.game.borrow().game().level() {
+        Some(level) => {
+            if pos.x < 0 || pos.y < 0 || pos.x >= level.width() || pos.y >= level.height() {
+                return EventResult::Consumed(None);
+            }
+            describe_square(level, pos)
+        }
+        None => { return EventResult::Consumed(None); }
+    };
+    state.cursor = Some(pos);
+    EventResult::with_cb(move |s| {
+        set_log_title(s, "Look ([Esc] to close)");
+        s.call_on_id(INFO_ID, |view: &mut TextView| {
+            view.set_content(text.clone());
+        });
+    })
+}
+
+fn describe_tile(tile: game::Tile) -> &'static str {
+    match tile {
+        game::Tile::Floor => "Floor.",
+        game::Tile::Wall | game::Tile::Secret => "A wall.",
+        game::Tile::Door => "A door.",
+        game::Tile::Exit => "The exit to the next level.",
+    }
+}
+
+fn describe_square(level: &game::Level, pos: game::Position) -> String {
+    let (tile, entity, fragment, visible) = match level.view(pos) {
+        game::Glyph::Unknown => { return "You haven't seen this spot yet.".to_string(); }
+        game::Glyph::Remembered(tile, fragment) => (tile, None, fragment, false),
+        game::Glyph::Visible(tile, entity, fragment) => (tile, entity, fragment, true),
+    };
+    let mut text = String::new();
+    match entity {
+        Some(typ) => text.push_str(&format!("{}.\n", game::capitalize(&typ.a()))),
+        None => {}
+    }
+    if let Some(enemy) = level.enemy_view(pos) {
+        let in_hand = enemy.cards.iter().filter(|c| c.in_hand).count();
+        text.push_str(&format!("Hand: {} of {} cards\n", in_hand, enemy.cards.len()));
+        for cv in &enemy.cards {
+            let name = cv.card.map(|c| c.name()).unwrap_or_else(|| "Unknown card".to_string());
+            text.push_str(&format!(
+                "  {} ({}{})\n",
+                name,
+                if cv.card.is_some() { "scanned" } else { "unknown" },
+                if cv.in_hand { "" } else { ", out of hand" },
+            ));
+        }
+        if !enemy.modifiers.is_empty() {
+            text.push_str("Modifiers:\n");
+            for mv in enemy.modifiers {
+                text.push_str(&format!("  {} (from {})\n", mv.modifier.name(), mv.source.a()));
+            }
+        }
+    }
+    text.push_str(describe_tile(tile));
+    if let Some(card) = fragment {
+        text.push_str(&format!("\nA fragment of {} code lies here.", card.name()));
+    }
+    if !visible {
+        text.push_str("\n(remembered, out of sight)");
+    }
+    text
+}
+
+fn new_game(s: &mut Cursive, game: &Rc<RefCell<game::Recorder>>, seed: u32) {
+    s.pop_layer();
+    let levels = game.borrow().levels();
+    *game.borrow_mut() = game::Recorder::with_length(seed, levels);
+    update_ui(s, game.borrow().game());
+    s.focus_id(MAP_ID).unwrap();
+}
+
+fn show_game_over(s: &mut Cursive, game: &Rc<RefCell<game::Recorder>>) {
+    let (text, seed) = match *game.borrow().game() {
+        game::Game::GameOver(ref death, _) => (
+            format!(
+                "You were deleted by {} on level {} after {} turns.\n\n{}.\n\nSeed: {}",
+                death.killer.a(), death.level, death.turns, death.cause, death.seed,
+            ),
+            death.seed,
+        ),
+        _ => { return; }
+    };
+    let same_seed = game.clone();
+    let new_seed = game.clone();
+    let history = game.clone();
+    s.add_layer(
+        Dialog::text(text)
+            .title("Game Over")
+            .button("History", move |s| show_history(s, history.borrow().game()))
+            .button("New game (same seed)", move |s| new_game(s, &same_seed, seed))
+            .button("New game (new seed)", move |s| new_game(s, &new_seed, rand::random()))
+    );
+}
+
+fn show_draft(s: &mut Cursive, game: &Rc<RefCell<game::Recorder>>) {
+    let mut select = SelectView::new();
+    let description = match *game.borrow().game() {
+        game::Game::Transition(ref trans) => {
+            for (i, &card) in trans.offers().iter().enumerate() {
+                select.add_item(format!("Add {}", card.name()), game::Mutation::Add(i));
+            }
+            for (i, (&card, count)) in trans.collected().iter().enumerate() {
+                select.add_item(
+                    format!("Add absorbed {} ({} left)", card.name(), count),
+                    game::Mutation::Absorb(i),
+                );
+            }
+            for (i, &card) in trans.deck().iter().enumerate() {
+                if let Some(upgraded) = card.upgraded() {
+                    select.add_item(
+                        format!("Upgrade {} to {}", card.name(), upgraded.name()),
+                        game::Mutation::Upgrade(i),
+                    );
+                }
+            }
+            for (i, &card) in trans.deck().iter().enumerate() {
+                if trans.can_mutate(game::Mutation::Remove(i)) {
+                    select.add_item(format!("Remove {}", card.name()), game::Mutation::Remove(i));
+                }
+            }
+            select.add_item("Leave the deck alone", game::Mutation::Skip);
+
+            let collected = trans.collected().iter()
+                .map(|(card, count)| format!("{} x{}", card.name(), count))
+                .collect::<Vec<_>>();
+            if collected.is_empty() {
+                format!("Your code mutates before level {}.", trans.next_level())
+            } else {
+                format!(
+                    "Your code mutates before level {}.\nAbsorbed: {}",
+                    trans.next_level(), collected.join(", "),
+                )
+            }
+        }
+        _ => { return; }
+    };
+    let game = game.clone();
+    select.set_on_submit(move |s, mutation: &game::Mutation| {
+        let mutated = game.borrow_mut().mutate(*mutation);
+        if mutated {
+            s.pop_layer();
+            update_ui(s, game.borrow().game());
+            s.focus_id(MAP_ID).unwrap();
+        }
+    });
+    s.add_layer(
+        Dialog::around(
+            LinearLayout::new(Orientation::Vertical)
+                .child(TextView::new(description))
+                .child(DummyView)
+                .child(select)
+        ).title("Mutate")
+    );
+}
+
+fn draw_map(state: &MapState, p: &Printer) {
+    let game = state.game.borrow();
+    let level = match *game.game() {
+        game::Game::Level(ref level) => level,
+        _ => { return; }
+    };
+    let (view_x, view_y) = (p.size.x as i32, p.size.y as i32);
+    let mut corner = state.camera.corner.get();
+    if !state.camera.free {
+        if let Some(focus) = state.cursor.or_else(|| level.player_position()) {
+            let margin = keymap::keymap().margin();
+            corner.x = follow_axis(corner.x, focus.x, view_x, margin);
+            corner.y = follow_axis(corner.y, focus.y, view_y, margin);
+        }
+    }
+    corner.x = clamp_axis(corner.x, view_x, level.width());
+    corner.y = clamp_axis(corner.y, view_y, level.height());
+    state.camera.corner.set(corner);
+    state.camera.size.set(p.size);
+
+    for x in 0..p.size.x {
+        for y in 0..p.size.y {
+            let pos = game::Position { x: corner.x + x as i32, y: corner.y + y as i32 };
+            let g = level.view(pos);
+            let is_cursor = state.cursor == Some(pos);
+            let color = if is_cursor {
+                ColorStyle::highlight()
+            } else {
+                colors::glyph_style(&g, p.focused)
+            };
+            p.with_color(color, |p| {
+                p.print(Vec2::new(x, y), &format!("{}", g.ch()));
+            });
+        }
+    }
+}
+
+fn describe_status(status: game::KnownCardStatus) -> String {
+    match status {
+        game::KnownCardStatus::Active => "In your hand.".to_string(),
+        game::KnownCardStatus::Inactive => "In your hand, but toggled off.".to_string(),
+        game::KnownCardStatus::Discarded => "Discarded. Rest to recover it.".to_string(),
+        game::KnownCardStatus::PlayedOnSelf => "Played on you.".to_string(),
+        game::KnownCardStatus::PlayedOnVisible(typ, pos) => {
+            format!("Played on {} at ({}, {}).", typ.the(), pos.x, pos.y)
+        }
+        game::KnownCardStatus::PlayedOnOther(typ) => {
+            format!("Played on {} you can't see.", typ.a())
+        }
+    }
+}
+
+fn show_card(s: &mut Cursive, cv: &game::CardView) {
+    let card = cv.card;
+    let mut text = format!("Trigger: {}\n\nEffect: {}\n\n", card.trigger(), card.effect());
+    if card.discards_itself() {
+        text.push_str("Discarded after it triggers.\n\n");
+    }
+    text.push_str(&describe_status(cv.status));
+    s.add_layer(Dialog::text(text).title(card.name()).dismiss_button("Close"));
+}
+
+struct ToggleInterceptorView<T: View> {
+    view: T,
+    game: Rc<RefCell<game::Recorder>>,
+    focused: Rc<Cell<bool>>,
+    interactive: bool,
+}
+
+impl<T: View> ToggleInterceptorView<T> {
+    fn new(
+        view: T,
+        game: Rc<RefCell<game::Recorder>>,
+        focused: Rc<Cell<bool>>,
+        interactive: bool,
+    ) -> Self {
+        Self { view, game, focused, interactive }
+    }
+}
+
+impl<T: View> ViewWrapper for ToggleInterceptorView<T> {
+    wrap_impl!(self.view: T);
+
+    fn wrap_on_event(&mut self, e: Event) -> EventResult {
+        match e {
+            Event::Char(ch) => {
+                if 'a' <= ch && ch <= 'z' {
+                    let game = self.game.clone();
+                    return EventResult::with_cb(move |s| {
+                        let card = match *game.borrow().game() {
+                            game::Game::Level(ref level) => {
+                                level.player_deck().into_iter().nth((ch as u8 - b'a') as usize)
+                            }
+                            _ => None,
+                        };
+                        if let Some(cv) = card {
+                            show_card(s, &cv);
+                        }
+                    });
+                }
+                if 'A' <= ch && ch <= 'Z' && self.interactive {
+                    let game = self.game.clone();
+                    return EventResult::with_cb(move |s| {
+                        let mut game = game.borrow_mut();
+                        let success = game.step(game::Action::Toggle((ch as u8 - b'A') as usize));
+                        if success {
+                            update_ui(s, game.game());
+                        }
+                    });
+                }
+            }
+            _ => {}
+        };
+        self.view.on_event(e)
+    }
+
+    fn wrap_draw(&self, printer: &Printer) {
+        self.focused.set(printer.focused);
+        self.view.draw(printer);
+    }
+}
+
+fn quit(s: &mut Cursive, game: &game::Recorder, save_path: &Option<PathBuf>) {
+    let path = match *save_path {
+        Some(ref path) => path,
+        None => { return s.quit(); }
+    };
+    match save_game(game, path) {
+        Ok(()) => s.quit(),
+        Err(e) => s.add_layer(
+            Dialog::text(format!("Unable to save game: {}", e))
+                .button("Quit anyway", |s| s.quit())
+                .dismiss_button("Cancel")
+        ),
+    }
+}
+
+// Also writes out a replay of the run next to the save, which is handy for bug reports
+fn save_game(game: &game::Recorder, path: &Path) -> io::Result<()> {
+    game.replay().save(&mut File::create(path.with_extension("replay"))?)?;
+    match *game.game() {
+        game::Game::GameOver(_, _) | game::Game::Victory(_) => match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+        _ => game.save(&mut File::create(path)?),
+    }
+}
+
+pub fn build_ui(siv: &mut Cursive, seed: u32) {
+    build_game_ui(siv, game::Recorder::new(seed), None, true);
+}
+
+/// Resumes the game saved at `save_path` if there is one (otherwise starts a new game of `levels`
+/// levels from `seed`) and saves the game back to `save_path` on quit.
+pub fn build_ui_with_save(siv: &mut Cursive, seed: u32, levels: i32, save_path: PathBuf) {
+    let loaded = File::open(&save_path).and_then(|mut f| game::Recorder::load(&mut f));
+    let (game, error) = match loaded {
+        Ok(game) => {
+            // Don't leave the save around to be loaded a second time
+            let _ = fs::remove_file(&save_path);
+            (game, None)
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            (game::Recorder::with_length(seed, levels), None)
+        }
+        Err(e) => (game::Recorder::with_length(seed, levels), Some(e)),
+    };
+    build_game_ui(siv, game, Some(save_path), true);
+    if let Some(e) = error {
+        siv.add_layer(Dialog::info(format!("Unable to load saved game: {}", e)));
+    }
+}
+
+struct ReplayState {
+    replay: game::Replay,
+    next: usize,
+    delay: u32,
+    ticks: u32,
+    paused: bool,
+}
+
+fn advance_replay(s: &mut Cursive, game: &Rc<RefCell<game::Recorder>>, state: &mut ReplayState) {
+    if state.paused || state.next >= state.replay.len() {
+        return;
+    }
+    state.ticks += 1;
+    if state.ticks < state.delay {
+        return;
+    }
+    state.ticks = 0;
+
+    let mut game = game.borrow_mut();
+    let applied = game.apply(state.replay.inputs[state.next]);
+    state.next += 1;
+    update_ui(s, game.game());
+    s.call_on_id(INFO_ID, |view: &mut TextView| {
+        view.scroll_bottom();
+    });
+
+    if !applied {
+        state.next = state.replay.len();
+        s.add_layer(Dialog::info(format!("Replay diverged at input {}.", state.next)));
+    } else if state.next == state.replay.len() {
+        s.add_layer(Dialog::info(if game.replay().hash == state.replay.hash {
+            "Replay finished. The final state matches the recording."
+        } else {
+            "Replay finished, but the final state does not match the recording."
+        }));
+    }
+}
+
+/// Plays back a recorded replay file. `+` and `-` adjust the playback speed and `p` pauses.
+pub fn build_replay_ui(siv: &mut Cursive, replay_path: &Path) -> io::Result<()> {
+    let replay = game::Replay::load(&mut File::open(replay_path)?)?;
+    let game = build_game_ui(
+        siv, game::Recorder::with_length(replay.seed, replay.levels), None, false,
+    );
+    let state = Rc::new(RefCell::new(ReplayState {
+        replay,
+        next: 0,
+        delay: REPLAY_DEFAULT_DELAY,
+        ticks: 0,
+        paused: false,
+    }));
+
+    siv.call_on_id(BUTTONS_ID, |view: &mut LinearLayout| {
+        view.add_child(BoxView::with_fixed_width(2, DummyView));
+        view.add_child(TextView::new("Replay: [+/-] Speed  [p] Pause"));
+    });
+    siv.set_fps(REPLAY_FPS);
+    {
+        let state = state.clone();
+        siv.add_global_callback(Event::Refresh, move |s| {
+            advance_replay(s, &game, &mut state.borrow_mut());
+        });
+    }
+    {
+        let state = state.clone();
+        siv.add_global_callback('+', move |_| {
+            let mut state = state.borrow_mut();
+            state.delay = (state.delay / 2).max(1);
+        });
+    }
+    {
+        let state = state.clone();
+        siv.add_global_callback('-', move |_| {
+            let mut state = state.borrow_mut();
+            state.delay = (state.delay * 2).min(REPLAY_MAX_DELAY);
+        });
+    }
+    siv.add_global_callback('p', move |_| {
+        let mut state = state.borrow_mut();
+        state.paused = !state.paused;
+    });
+    Ok(())
+}
+
+fn build_game_ui(
+    siv: &mut Cursive,
+    game: game::Recorder,
+    save_path: Option<PathBuf>,
+    interactive: bool,
+) -> Rc<RefCell<game::Recorder>> {
+    let game = Rc::new(RefCell::new(game));
+
+    siv.set_theme(colors::theme());
+
+    // TODO: add a key binding for abandoning the current run and starting a new one
+    for (event, command) in keymap::keymap().global_bindings() {
+        match command {
+            Command::Help => siv.add_global_callback(event, show_help),
+            Command::FocusMap => siv.add_global_callback(event, |s| s.focus_id(MAP_ID).unwrap()),
+            Command::Quit => {
+                let (game, save_path) = (game.clone(), save_path.clone());
+                siv.add_global_callback(event, move |s| quit(s, &game.borrow(), &save_path));
+            }
+            _ => {}
+        }
+    }
+
+    siv.add_fullscreen_layer(BoxView::with_full_screen(
+        LinearLayout::new(Orientation::Vertical)
+            .child(BoxView::with_full_width(
+                IsolateFocusView::new(
+                    // TODO: add buttons for rest and wait
+                    LinearLayout::new(Orientation::Horizontal)
+                        .child(BoxView::with_fixed_width(1, DummyView))
+                        // TODO: Make quit button conditional on target (hide for WASM)
+                        // .child(Button::new("[Ctrl+q] Quit", |s| s.quit()).with_id(QUIT_ID))
+                        // .child(BoxView::with_fixed_width(2, DummyView))
+                        .child(Button::new("[?] Help", show_help))
+                        // .child(BoxView::with_fixed_width(2, DummyView))
+                        // .child(Button::new("[Space] Restore focus", |s| s.focus_id(MAP_ID).unwrap()))
+                        .with_id(BUTTONS_ID)
+                )
+            ))
+            .child(BoxView::with_full_screen(
+                LinearLayout::new(Orientation::Horizontal)
+                    .child(BoxView::with_full_screen(
+                        LinearLayout::new(Orientation::Vertical)
+                            .child(TextView::new("").with_id(STATUS_ID))
+                            .child(BoxView::with_full_screen(Panel::new(
+                                Canvas::new(MapState {
+                                    game: game.clone(),
+                                    cursor: None,
+                                    camera: Camera::new(),
+                                })
+                                    .with_take_focus(|_, dir| dir == Direction::Abs(Absolute::None))
+                                    .with_on_event(move |state, e| if interactive {
+                                        process_map_event(state, e)
+                                    } else {
+                                        EventResult::Ignored
+                                    })
+                                    .with_draw(draw_map)
+                                    .with_id(MAP_ID)
+                            ).with_id(MAIN_PANEL_ID)))
+                    ))
+                    .child(BoxView::with_fixed_width(
+                        41,
+                        IsolateFocusView::new({
+                            let focused = Rc::new(Cell::new(false));
+                            ToggleInterceptorView::new(
+                                Dialog::around(
+                                    LinearLayout::new(Orientation::Vertical)
+                                        .child(BoxView::with_full_height(
+                                            TextView::new("")
+                                                .scrollable(true)
+                                                .with_id(CARDS_ID)
+                                        ))
+                                        .child(BoxView::with_fixed_height(
+                                            1,
+                                            // TODO: Don't include Toggle if exiting level
+                                            Canvas::wrap(TextView::new(
+                                                StyledString::single_span(
+                                                    "[a-z] Examine  [A-Z] Toggle",
+                                                    ColorStyle::secondary().into(),
+                                                )
+                                            ))
+                                                .with_draw({
+                                                    let focused = focused.clone();
+                                                    move |v, p| {
+                                                        if focused.get() {
+                                                            v.draw(p);
+                                                        }
+                                                    }
+                                                }),
+                                        ))
+                                )
+                                    .title("Deck")
+                                    .title_position(HAlign::Left)
+                                    .with_id(CARDS_DIALOG_ID),
+                                game.clone(),
+                                focused,
+                                interactive,
+                            )
+                        }),
+                    ))
+            ))
+            .child(BoxView::with_fixed_height(
+                INFO_HEIGHT,
+                IsolateFocusView::new({
+                    let mut view = OnEventView::new(
+                        Dialog::around(
+                            TextView::new("")
+                                .scrollable(true)
+                                .scroll_strategy(ScrollStrategy::StickToBottom)
+                                .with_id(INFO_ID)
+                        )
+                            .title(log_title())
+                            .title_position(HAlign::Left)
+                            .with_id(INFO_DIALOG_ID)
+                    ).on_event('i', |s| s.focus_id(CARDS_ID).unwrap());
+                    for (i, &(kind, _)) in LOG_KIND_NAMES.iter().enumerate() {
+                        let game = game.clone();
+                        view = view.on_event((b'1' + i as u8) as char, move |s| {
+                            toggle_log_kind(s, kind, game.borrow().game());
+                        });
+                    }
+                    view
+                }),
+            ))
+    ));
+
+    update_ui(siv, game.borrow().game());
+    // siv.focus_id(QUIT_ID).unwrap();
+    siv.focus_id(MAP_ID).unwrap();
+    if interactive {
+        show_game_over(siv, &game);
+        show_draft(siv, &game);
+    }
+    game
+}
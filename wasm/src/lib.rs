@@ -13,7 +13,7 @@ use cyberphage::cursive::{
     Cursive,
     event::{Event, Key, MouseButton, MouseEvent},
     backend::Backend,
-    theme::{BaseColor, Color, ColorPair, Effect},
+    theme::{Color, ColorPair, Effect},
     vec::Vec2,
 };
 
@@ -166,32 +166,9 @@ fn key_code_to_key(key_code: u32) -> Option<Key> {
     })
 }
 
+// Shares the game's colour table, so the browser shows the same colours as a terminal would
 fn color_to_rgb(color: Color) -> Option<[u8; 3]> {
-    Some(match color {
-        Color::TerminalDefault => { return None; }
-        Color::Dark(color) => match color {
-            BaseColor::Black => [0x00, 0x00, 0x00],
-            BaseColor::Red => [0x80, 0x00, 0x00],
-            BaseColor::Green => [0x00, 0x80, 0x00],
-            BaseColor::Yellow => [0x80, 0x80, 0x00],
-            BaseColor::Blue => [0x00, 0x00, 0x80],
-            BaseColor::Magenta => [0x80, 0x00, 0x80],
-            BaseColor::Cyan => [0x00, 0x80, 0x80],
-            BaseColor::White => [0xc0, 0xc0, 0xc0],
-        },
-        Color::Light(color) => match color {
-            BaseColor::Black => [0x80, 0x80, 0x80],
-            BaseColor::Red => [0xff, 0x00, 0x00],
-            BaseColor::Green => [0x00, 0xff, 0x00],
-            BaseColor::Yellow => [0xff, 0xff, 0x00],
-            BaseColor::Blue => [0x00, 0x00, 0xff],
-            BaseColor::Magenta => [0xff, 0x00, 0xff],
-            BaseColor::Cyan => [0x00, 0xff, 0xff],
-            BaseColor::White => [0xff, 0xff, 0xff],
-        },
-        Color::Rgb(r, g, b) => [r, g, b],
-        Color::RgbLowRes(r, g, b) => [r*51, g*51, b*51],
-    })
+    cyberphage::colors::rgb(color)
 }
 
 fn rgb_to_u32(rgb: [u8; 3]) -> u32 {